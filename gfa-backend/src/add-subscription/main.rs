@@ -1,14 +1,18 @@
 use aws_lambda_events::event::apigw::{ApiGatewayV2httpRequest, ApiGatewayV2httpResponse};
 use common::send_email::send_email;
 use common::subscription::Subscription;
-use common::subscriptions_repo::{get_subscription, store_subscription};
-use common::stops_repo::get_single_stop;
+use common::pickup_stop::PickUpStop;
+use common::subscriptions_repo::{SubscriptionsRepository, DynamoDbSubscriptionsRepository};
+use common::stops_repo::{StopsRepository, DynamoDbStopsRepository};
+use common::city_profile::CityProfile;
 use lambda::{handler_fn, Context};
 use log::{self, error, LevelFilter};
 use rusoto_core::Region;
 use simple_logger::SimpleLogger;
 use std::{collections::HashMap, env, str::FromStr};
 
+use add_subscription_request::AddSubscriptionRequest;
+
 mod add_subscription_request;
 mod parser;
 mod verification_email;
@@ -30,9 +34,11 @@ async fn handle_request(
     let subscriptions_table = env::var("SUBSCRIPTIONS_TABLE").unwrap();
     let events_table = env::var("EVENTS_TABLE").unwrap();
     let location_index = env::var("LOCATION_INDEX").unwrap();
+    let aliases_table = env::var("ALIASES_TABLE").unwrap();
     let verify_url = env::var("VERIFY_URL").unwrap();
     let api_key = env::var("SENDGRID_API_KEY").unwrap();
     let email_domain = env::var("EMAIL_DOMAIN").unwrap();
+    let profile = CityProfile::from_env()?;
     let region = env::var("AWS_REGION").unwrap();
     let region = Region::from_str(&region).unwrap();
 
@@ -41,10 +47,43 @@ async fn handle_request(
         Err(e) => return Ok(create_response(e.status_code, e.message))
     };
 
-    match get_subscription(&subscriptions_table, &region, &request.email, &request.location_id,).await {
+    let stops = DynamoDbStopsRepository::new(&region, &events_table, &location_index, &aliases_table);
+    let subscriptions = DynamoDbSubscriptionsRepository::new(&region, &subscriptions_table);
+    let (subscription, stop) = match add_subscription(&stops, &subscriptions, &request).await {
+        Ok(added) => added,
+        Err(response) => return Ok(response)
+    };
+
+    let email_request = verification_email::create_request(&subscription, &stop, &profile, &email_domain, &verify_url); 
+    match send_email(&api_key, email_request).await {
+        Ok(_response) => Ok(create_response(200, "Successfully created subscription".to_owned())),
+        Err(error) => {
+            error!("Failed to send verification email: {}", error);
+            Ok(create_response(500, "Failed to send verification email".to_owned()))
+        }
+    }
+}
+
+/// Stores an unverified subscription, or returns the response to give if it can not be added.
+async fn add_subscription(stops: &dyn StopsRepository, subscriptions: &dyn SubscriptionsRepository, request: &AddSubscriptionRequest) -> Result<(Subscription, PickUpStop), ApiGatewayV2httpResponse> {
+    // The stop is looked up first, so that subscriptions are always made with the current id of a location
+    let stop = match stops.get_single_stop(&request.location_id).await {
+        Ok(optional_stop) => match optional_stop {
+            Some(stop) => stop,
+            None => {
+                return Err(create_response(400, format!("Location does not exist: {}", request.location_id)));
+            }
+        },
+        Err(error) => {
+            error!("Failed to read from database: {}", error);
+            return Err(create_response(500, "Failed to read from database".to_owned()));
+        }
+    };
+
+    match subscriptions.get_subscription(&request.email, &stop.location_id).await {
         Ok(optional_subscription) => if let Some(subscription) = optional_subscription {
             if subscription.is_authenticated {
-                return Ok(create_response(
+                return Err(create_response(
                     400,
                     "Subscription already exist for this e-mail address and location".to_owned(),
                 ));
@@ -52,46 +91,26 @@ async fn handle_request(
         },
         Err(error) => {
             error!("Failed to read from database: {}", error);
-            return Ok(create_response(
+            return Err(create_response(
                 500,
                 "Failed to read from database".to_owned(),
             ));
         }
     }
 
-    let stop = match get_single_stop(&events_table, &region, &location_index, &request.location_id).await {
-        Ok(optional_stop) => match optional_stop {
-            Some(stop) => stop,
-            None => {
-                return Ok(create_response(400, format!("Location does not exist: {}", request.location_id)));
-            }
-        },
-        Err(error) => {
-            error!("Failed to read from database: {}", error);
-            return Ok(create_response(500, "Failed to read from database".to_owned()));
-        }
-    };
-
-    let subscription = Subscription::new(&request.email, &request.location_id);
-    match store_subscription(&subscriptions_table, &region, &subscription).await {
+    let subscription = Subscription::new(&request.email, &stop.location_id);
+    match subscriptions.store_subscription(&subscription).await {
         Ok(()) => (),
         Err(error) => {
             error!("Failed to write to database: {}", error);
-            return Ok(create_response(
+            return Err(create_response(
                 500,
                 "Failed to write to database".to_owned(),
             ));
         }
     };
 
-    let email_request = verification_email::create_request(&subscription, &stop, &email_domain, &verify_url); 
-    match send_email(&api_key, email_request).await {
-        Ok(_response) => Ok(create_response(200, "Successfully created subscription".to_owned())),
-        Err(error) => {
-            error!("Failed to send verification email: {}", error);
-            Ok(create_response(500, "Failed to send verification email".to_owned()))
-        }
-    }
+    Ok((subscription, stop))
 }
 
 fn create_response(status_code: i64, body: String) -> ApiGatewayV2httpResponse {
@@ -104,3 +123,53 @@ fn create_response(status_code: i64, body: String) -> ApiGatewayV2httpResponse {
         cookies: Vec::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::LocationAlias;
+    use common::stops_repo::InMemoryStopsRepository;
+    use common::subscriptions_repo::InMemorySubscriptionsRepository;
+
+    fn stops() -> InMemoryStopsRepository {
+        InMemoryStopsRepository::with_stops(vec![
+            PickUpStop::new("farligt-avfall:centrum_storgatan".to_owned(), "Storgatan".to_owned(), "Centrum".to_owned(), None, None),
+        ])
+    }
+
+    fn request(location_id: &str) -> AddSubscriptionRequest {
+        AddSubscriptionRequest{
+            email: "email@email.com".to_owned(),
+            location_id: location_id.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_subscribe_with_current_location_id() {
+        let stops = stops();
+        stops.store_aliases(&[LocationAlias{
+            alias_id: "centrum_storgatan".to_owned(),
+            location_id: "farligt-avfall:centrum_storgatan".to_owned(),
+        }]).await.unwrap();
+        let subscriptions = InMemorySubscriptionsRepository::default();
+        let (subscription, _stop) = add_subscription(&stops, &subscriptions, &request("centrum_storgatan")).await.unwrap();
+        assert_eq!("farligt-avfall:centrum_storgatan", subscription.location_id);
+        assert_eq!(Some(subscription), subscriptions.get_subscription("email@email.com", "farligt-avfall:centrum_storgatan").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_not_subscribe_to_unknown_location() {
+        let subscriptions = InMemorySubscriptionsRepository::default();
+        let response = add_subscription(&stops(), &subscriptions, &request("farligt-avfall:centrum_okand")).await.unwrap_err();
+        assert_eq!(400, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn should_not_subscribe_twice() {
+        let mut subscription = Subscription::new("email@email.com", "farligt-avfall:centrum_storgatan");
+        subscription.verify();
+        let subscriptions = InMemorySubscriptionsRepository::with_subscriptions(vec![subscription]);
+        let response = add_subscription(&stops(), &subscriptions, &request("farligt-avfall:centrum_storgatan")).await.unwrap_err();
+        assert_eq!(400, response.status_code);
+    }
+}
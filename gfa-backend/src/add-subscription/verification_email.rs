@@ -2,13 +2,14 @@ use std::collections::HashMap;
 use common::send_email::{From, Recipient, SendEmailRequest};
 use common::subscription::Subscription;
 use common::pickup_stop::PickUpStop;
+use common::city_profile::CityProfile;
 
-pub fn create_request(subscription: &Subscription, stop: &PickUpStop, email_domain: &str, verify_url: &str) -> SendEmailRequest {
+pub fn create_request(subscription: &Subscription, stop: &PickUpStop, profile: &CityProfile, email_domain: &str, verify_url: &str) -> SendEmailRequest {
     let html_content = include_str!("verification_email.html");
     SendEmailRequest {
         from: From {
-            name: "Göteborg Farligt Avfall Notifications".to_owned(),
-            email: format!("noreply-farligtavfall@{}", email_domain),
+            name: profile.sender.name.clone(),
+            email: profile.sender.address(email_domain),
         },
         subject: "Please verify your subscription".to_owned(),
         recipients: vec![Recipient {
@@ -17,6 +18,8 @@ pub fn create_request(subscription: &Subscription, stop: &PickUpStop, email_doma
                 ("-verifyUrl-".to_owned(), format!("{}?email={}&auth_token={}", verify_url, subscription.email.to_owned(), subscription.auth_token.to_owned().unwrap())),
                 ("-street-".to_owned(), stop.street.clone()),
                 ("-district-".to_owned(), stop.district.clone()),
+                ("-sender-".to_owned(), profile.sender.name.clone()),
+                ("-city-".to_owned(), profile.name.clone()),
             ]
             .iter()
             .cloned()
@@ -0,0 +1,186 @@
+use std::{env, fmt, error};
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use crate::pickup_event::FARLIGT_AVFALL_SERVICE_ID;
+
+pub const DEFAULT_CITY_PROFILE: &str = "goteborg";
+
+#[derive(fmt::Debug)]
+pub struct CityProfileError {
+    pub message: String,
+}
+impl fmt::Display for CityProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for CityProfileError {}
+
+/// Everything which differs between the municipalities a deployment can serve.
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CityProfile {
+    pub id: String,
+    pub name: String,
+    pub sources: Vec<SourceProfile>,
+    #[serde(with = "time_zone_name")]
+    pub time_zone: Tz,
+    /// BCP 47 language tag of the schedule pages and emails, e.g. "sv-SE".
+    pub locale: String,
+    pub sender: SenderIdentity,
+}
+
+/// Where the schedule of one collection service is published, and how its pages are parsed.
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceProfile {
+    pub service_id: String,
+    /// What the service is called in emails, e.g. "Farligt Avfall-bilen" or "Miljöbilen".
+    pub display_name: String,
+    pub base_url: String,
+    /// Path of the first schedule page, relative to `base_url`.
+    pub path: String,
+    pub parser: ParserKind,
+}
+
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParserKind {
+    /// Paginated lists of c-snippet elements with Swedish schedules, as published on goteborg.se.
+    GoteborgSnippets,
+}
+
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SenderIdentity {
+    pub name: String,
+    /// The part before the @ of the sender address. The domain is given by each deployment.
+    pub local_part: String,
+}
+
+impl SenderIdentity {
+    pub fn address(&self, email_domain: &str) -> String {
+        format!("{}@{}", self.local_part, email_domain)
+    }
+}
+
+impl CityProfile {
+    pub fn goteborg() -> Self {
+        CityProfile{
+            id: "goteborg".to_owned(),
+            name: "Göteborg".to_owned(),
+            sources: vec![SourceProfile{
+                service_id: FARLIGT_AVFALL_SERVICE_ID.to_owned(),
+                display_name: "Farligt Avfall-bilen".to_owned(),
+                base_url: "https://goteborg.se".to_owned(),
+                path: "/wps/portal/start/avfall-och-atervinning/har-lamnar-hushall-avfall/farligtavfallbilen/farligt-avfall-bilen".to_owned(),
+                parser: ParserKind::GoteborgSnippets,
+            }],
+            time_zone: Tz::Europe__Stockholm,
+            locale: "sv-SE".to_owned(),
+            sender: SenderIdentity{
+                name: "Göteborg Farligt Avfall Notifications".to_owned(),
+                local_part: "noreply-farligtavfall".to_owned(),
+            },
+        }
+    }
+
+    pub fn builtin(id: &str) -> Option<Self> {
+        match id {
+            "goteborg" => Some(CityProfile::goteborg()),
+            _ => None,
+        }
+    }
+
+    /// Reads a complete profile from CITY_PROFILE_JSON if set, otherwise the built-in profile named by CITY_PROFILE.
+    /// Deployments for municipalities without a built-in profile, e.g. Mölndal or Partille, use CITY_PROFILE_JSON.
+    pub fn from_env() -> Result<Self, CityProfileError> {
+        if let Ok(json) = env::var("CITY_PROFILE_JSON") {
+            return CityProfile::from_json(&json);
+        }
+        let id = env::var("CITY_PROFILE").unwrap_or_else(|_| DEFAULT_CITY_PROFILE.to_owned());
+        match CityProfile::builtin(&id) {
+            Some(profile) => Ok(profile),
+            None => Err(CityProfileError{
+                message: format!("No built-in city profile named {}, use CITY_PROFILE_JSON to configure it", id)
+            })
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, CityProfileError> {
+        let profile: CityProfile = match serde_json::from_str(json) {
+            Ok(profile) => profile,
+            Err(e) => return Err(CityProfileError{
+                message: format!("Malformed city profile: {}", e)
+            })
+        };
+        if profile.sources.is_empty() {
+            return Err(CityProfileError{
+                message: format!("City profile {} has no sources", profile.id)
+            });
+        }
+        Ok(profile)
+    }
+
+    pub fn source(&self, service_id: &str) -> Option<&SourceProfile> {
+        self.sources.iter()
+            .find(|source| source.service_id == service_id)
+    }
+
+    pub fn local_date(&self, datetime: DateTime<Utc>) -> NaiveDate {
+        datetime.with_timezone(&self.time_zone).date().naive_local()
+    }
+}
+
+mod time_zone_name {
+    use std::str::FromStr;
+    use chrono_tz::Tz;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(time_zone: &Tz, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(time_zone.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tz, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Tz::from_str(&name).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_profile_from_json() {
+        let json = r#"{
+            "id": "molndal",
+            "name": "Mölndal",
+            "sources": [{"service_id": "farligt-avfall", "display_name": "Farligt Avfall-bilen", "base_url": "https://example.com", "path": "/schema", "parser": "goteborg-snippets"}],
+            "time_zone": "Europe/Stockholm",
+            "locale": "sv-SE",
+            "sender": {"name": "Mölndal Farligt Avfall Notifications", "local_part": "noreply-molndal"}
+        }"#;
+        let profile = CityProfile::from_json(json).unwrap();
+        assert_eq!(Tz::Europe__Stockholm, profile.time_zone);
+        assert_eq!("https://example.com", profile.source("farligt-avfall").unwrap().base_url);
+        assert_eq!("noreply-molndal@example.com", profile.sender.address("example.com"));
+    }
+
+    #[test]
+    fn should_round_trip_builtin_profile() {
+        let json = serde_json::to_string(&CityProfile::goteborg()).unwrap();
+        assert_eq!(CityProfile::goteborg(), CityProfile::from_json(&json).unwrap());
+    }
+
+    #[test]
+    fn should_reject_unknown_time_zone() {
+        let json = serde_json::to_string(&CityProfile::goteborg()).unwrap()
+            .replace("Europe/Stockholm", "Europe/Atlantis");
+        assert!(CityProfile::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn should_use_local_date() {
+        let datetime = DateTime::parse_from_rfc3339("2021-03-01T23:30:00+00:00").unwrap().with_timezone(&Utc);
+        assert_eq!(NaiveDate::from_ymd(2021, 3, 2), CityProfile::goteborg().local_date(datetime));
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt;
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Clone, fmt::Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, fmt::Debug)]
 pub struct Coordinate {
     latitude: f64,
     longitude: f64,
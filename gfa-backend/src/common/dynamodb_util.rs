@@ -1,11 +1,174 @@
-use std::fmt;
-use std::error;
+use std::env;
+use std::future::Future;
+use std::collections::HashMap;
+use futures::{stream, Stream, TryStreamExt};
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, QueryInput, QueryError, ScanInput, ScanError};
+use crate::coordinate::Coordinate;
 
-#[derive(Debug)]
-pub struct MalformedDynamoDbResponse;
-impl fmt::Display for MalformedDynamoDbResponse {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Malformed response from DynamoDb")
+/// Creates a client for the region, or for the endpoint in DYNAMODB_ENDPOINT if it is set,
+/// e.g. "http://localhost:8000" to run against DynamoDB Local.
+pub fn client(region: &Region) -> DynamoDbClient {
+    DynamoDbClient::new(endpoint_region(region, env::var("DYNAMODB_ENDPOINT").ok()))
+}
+
+fn endpoint_region(region: &Region, endpoint: Option<String>) -> Region {
+    match endpoint {
+        Some(endpoint) => Region::Custom{
+            name: region.name().to_owned(),
+            endpoint,
+        },
+        None => region.clone(),
+    }
+}
+
+pub type Item = HashMap<String, AttributeValue>;
+
+/// One page of a scan or query.
+pub struct Page {
+    pub items: Vec<Item>,
+    pub last_evaluated_key: Option<Item>,
+}
+
+/// Reads a scan page by page, so that no items are missed once the result is larger than the 1 MB DynamoDB returns at a time.
+pub fn scan(client: &DynamoDbClient, input: ScanInput) -> impl Stream<Item = Result<Item, RusotoError<ScanError>>> + '_ {
+    paginate(move |exclusive_start_key| {
+        let input = ScanInput{
+            exclusive_start_key,
+            ..input.clone()
+        };
+        async move {
+            let output = client.scan(input).await?;
+            Ok(Page{
+                items: output.items.unwrap_or_default(),
+                last_evaluated_key: output.last_evaluated_key,
+            })
+        }
+    })
+}
+
+/// Reads a query page by page, like `scan`.
+pub fn query(client: &DynamoDbClient, input: QueryInput) -> impl Stream<Item = Result<Item, RusotoError<QueryError>>> + '_ {
+    paginate(move |exclusive_start_key| {
+        let input = QueryInput{
+            exclusive_start_key,
+            ..input.clone()
+        };
+        async move {
+            let output = client.query(input).await?;
+            Ok(Page{
+                items: output.items.unwrap_or_default(),
+                last_evaluated_key: output.last_evaluated_key,
+            })
+        }
+    })
+}
+
+/// Fetches pages with the last evaluated key of the previous page, until a page has none. Pages are only
+/// fetched as the stream is read, so taking the first few items only reads the pages which hold them.
+fn paginate<'a, F, Fut, E>(fetch_page: F) -> impl Stream<Item = Result<Item, E>> + 'a
+where
+    F: Fn(Option<Item>) -> Fut + 'a,
+    Fut: Future<Output = Result<Page, E>> + 'a,
+    E: 'a,
+{
+    // None when there are no more pages, otherwise the key to start the next page from
+    let first_page: Option<Option<Item>> = Some(None);
+    stream::try_unfold(first_page, move |next_page| {
+        let page = next_page.map(&fetch_page);
+        async move {
+            match page {
+                Some(page) => {
+                    let page = page.await?;
+                    Ok(Some((page.items, page.last_evaluated_key.map(Some))))
+                },
+                None => Ok(None),
+            }
+        }
+    })
+    .map_ok(|items| stream::iter(items.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+/// Adds a coordinate to an item as the number attributes latitude and longitude.
+pub fn insert_coordinate(item: &mut HashMap<String, AttributeValue>, coordinate: &Coordinate) {
+    item.insert("latitude".to_owned(), AttributeValue{
+        n: Some(coordinate.latitude().to_string()),
+        ..Default::default()
+    });
+    item.insert("longitude".to_owned(), AttributeValue{
+        n: Some(coordinate.longitude().to_string()),
+        ..Default::default()
+    });
+}
+
+pub fn item_to_coordinate(item: &HashMap<String, AttributeValue>) -> Option<Coordinate> {
+    let latitude = item.get("latitude")?.n.as_ref()?.parse::<f64>().ok()?;
+    let longitude = item.get("longitude")?.n.as_ref()?.parse::<f64>().ok()?;
+    Some(Coordinate::new(latitude, longitude))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_inserted_coordinate() {
+        let mut item = HashMap::new();
+        insert_coordinate(&mut item, &Coordinate::new(57.7072, 11.9668));
+        assert_eq!(Some(Coordinate::new(57.7072, 11.9668)), item_to_coordinate(&item));
+    }
+
+    #[test]
+    fn should_not_read_missing_coordinate() {
+        assert_eq!(None, item_to_coordinate(&HashMap::new()));
+    }
+
+    #[test]
+    fn should_use_local_endpoint() {
+        let region = endpoint_region(&Region::EuWest1, Some("http://localhost:8000".to_owned()));
+        assert_eq!(Region::Custom{ name: "eu-west-1".to_owned(), endpoint: "http://localhost:8000".to_owned() }, region);
+        assert_eq!(Region::EuWest1, endpoint_region(&Region::EuWest1, None));
+    }
+
+    fn item(id: &str) -> Item {
+        [("id".to_owned(), AttributeValue{s: Some(id.to_owned()), ..Default::default()})]
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_read_every_page() {
+        // The second page is empty but not the last one, which happens when a filter excludes a whole page
+        let pages = [
+            (None, vec!["a", "b"], Some("b")),
+            (Some("b"), vec![], Some("c")),
+            (Some("c"), vec!["d"], None),
+        ];
+        let fetch_page = |exclusive_start_key: Option<Item>| {
+            let (_, ids, last) = pages.iter()
+                .find(|(start, _, _)| start.map(item) == exclusive_start_key)
+                .unwrap();
+            let page = Page{
+                items: ids.iter().map(|id| item(id)).collect(),
+                last_evaluated_key: last.map(item),
+            };
+            async move { Ok::<Page, String>(page) }
+        };
+        let items: Vec<Item> = paginate(fetch_page).try_collect().await.unwrap();
+        assert_eq!(vec![item("a"), item("b"), item("d")], items);
+    }
+
+    #[tokio::test]
+    async fn should_stop_at_failed_page() {
+        let fetch_page = |exclusive_start_key: Option<Item>| async move {
+            match exclusive_start_key {
+                None => Ok(Page{ items: vec![item("a")], last_evaluated_key: Some(item("a")) }),
+                Some(_) => Err("Throttled".to_owned()),
+            }
+        };
+        let result: Result<Vec<Item>, String> = paginate(fetch_page).try_collect().await;
+        assert_eq!(Err("Throttled".to_owned()), result);
     }
 }
-impl error::Error for MalformedDynamoDbResponse {}
@@ -1,18 +1,34 @@
-use std::{fmt, error, collections::HashMap};
-use crate::pickup_event::PickUpEvent;
-use rusoto_dynamodb::{DynamoDb, DynamoDbClient, WriteRequest, PutRequest, AttributeValue, BatchWriteItemInput, BatchWriteItemError, QueryInput};
+use std::{fmt, error, str::FromStr, collections::{BTreeMap, HashMap}, future::Future, cmp::min, sync::Mutex};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use futures::{stream, StreamExt, TryStreamExt};
+use log::warn;
+use rand::Rng;
+use tokio::time::{self, Duration, Instant};
+use crate::pickup_event::{PickUpEvent, EventKey, EventStatus, parse_time};
+use crate::time_format::iso_date;
+use crate::dynamodb_util::{self, Item, insert_coordinate, item_to_coordinate};
+use rusoto_dynamodb::{DynamoDb, DynamoDbClient, WriteRequest, PutRequest, DeleteRequest, AttributeValue, BatchWriteItemInput, BatchWriteItemError, QueryInput, ScanInput};
 use rusoto_core::{Region, RusotoError};
 
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// DynamoDB accepts at most 25 writes in one batch.
+const BATCH_SIZE: usize = 25;
+const CONCURRENT_BATCHES: usize = 4;
+
 #[derive(fmt::Debug)]
 pub struct EventsRepoError {
     pub errors: Vec<RusotoError<BatchWriteItemError>>,
+    /// Keys of the events which were not written, because their batch failed.
+    pub failed: Vec<EventKey>,
 }
 impl fmt::Display for EventsRepoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for error in &self.errors {
             writeln!(f, "{}", error)?;
         }
-        write!(f, "Total db errors while writing events: {}", self.errors.len())
+        write!(f, "Total db errors while writing events: {}, events not written: {}", self.errors.len(), self.failed.len())
     }
 }
 impl error::Error for EventsRepoError {
@@ -21,104 +37,608 @@ impl error::Error for EventsRepoError {
     }
 }
 
-pub async fn get_by_date(table: String, region: Region, date: String) -> Result<Vec<PickUpEvent>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let client = DynamoDbClient::new(region);
-    let mut attribute_values = HashMap::new();
-    attribute_values.insert(":date".to_owned(), AttributeValue{
-        s: Some(date),
-        ..Default::default()
-    });
-    let events: Vec<PickUpEvent> = client
-        .query(QueryInput{
-            table_name: table,
-            expression_attribute_values: Some(attribute_values),
-            key_condition_expression: Some("event_date = :date".to_owned()),
-            ..Default::default()
-        })
-        .await?
-        .items
-        .unwrap_or_else(Vec::new)
-        .into_iter()
-        .map(|item| {
-            PickUpEvent::new_with_id(
-                item.get("location_id").unwrap().s.as_ref().unwrap().clone(),
-                item.get("street").unwrap().s.as_ref().unwrap().clone(),
-                item.get("district").unwrap().s.as_ref().unwrap().clone(),
-                item.get("description").map(|description| description.s.as_ref().unwrap().clone()),
-                item.get("start_time").unwrap().s.as_ref().unwrap().clone(),
-                item.get("end_time").unwrap().s.as_ref().unwrap().clone(),
-            ).unwrap()
-        })
-        .collect();
-    Ok(events)
+/// The result of writing events which DynamoDB accepted. Writes which were still unprocessed at the deadline,
+/// typically because the table was throttled, are in `failed`.
+#[derive(fmt::Debug, Default, PartialEq)]
+pub struct WriteOutcome {
+    pub written: usize,
+    /// The number of writes which were sent again, counted once per retry.
+    pub retried: usize,
+    pub failed: Vec<EventKey>,
 }
 
-pub async fn store(table: String, region: Region, events: Vec::<PickUpEvent>) -> Result<usize, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let client = DynamoDbClient::new(region);
-    let write_requests: Vec<WriteRequest> = events.into_iter()
-        .map(|event| {
-            let mut attributes: HashMap<String, AttributeValue> = HashMap::new(); 
-            attributes.insert("event_date".to_owned(), AttributeValue{
-                s: Some(event.date),
-                ..Default::default()
-            });
-            attributes.insert("location_id".to_owned(), AttributeValue{
-                s: Some(event.location_id),
-                ..Default::default()
-            });
-            attributes.insert("district".to_owned(), AttributeValue{
-                s: Some(event.district),
-                ..Default::default()
-            });
-            attributes.insert("street".to_owned(), AttributeValue{
-                s: Some(event.street),
+/// How unprocessed writes are retried.
+#[derive(fmt::Debug, Clone, Copy)]
+struct RetryPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// No retry is started after this time has passed since the first write.
+    deadline: Duration,
+}
+
+const RETRY_POLICY: RetryPolicy = RetryPolicy{
+    initial_backoff: Duration::from_millis(50),
+    max_backoff: Duration::from_secs(5),
+    deadline: Duration::from_secs(30),
+};
+
+#[derive(fmt::Debug)]
+pub struct MalformedEvent {
+    pub message: String,
+}
+impl fmt::Display for MalformedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Malformed event in database: {}", self.message)
+    }
+}
+impl error::Error for MalformedEvent {}
+
+#[async_trait]
+pub trait EventsRepository: Send + Sync {
+    /// Returns the events of a date, sorted by location and start time, so that several visits to a stop come in order.
+    async fn get_by_date(&self, date: NaiveDate) -> Result<Vec<PickUpEvent>, Error>;
+    /// Returns all events on or after `from_date`.
+    async fn get_from_date(&self, from_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error>;
+    /// Returns the events of a location from `from_date` up to and including `to_date`, sorted by start time.
+    async fn get_by_location(&self, location_id: &str, from_date: NaiveDate, to_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error>;
+    async fn store(&self, events: Vec<PickUpEvent>) -> Result<WriteOutcome, EventsRepoError>;
+    /// Deletes events, e.g. the previous version of a visit which has got a new time.
+    async fn delete(&self, keys: &[EventKey]) -> Result<WriteOutcome, EventsRepoError>;
+}
+
+pub struct DynamoDbEventsRepository {
+    client: DynamoDbClient,
+    table: String,
+    /// Index of the events table by location id and date.
+    location_index: String,
+}
+
+impl DynamoDbEventsRepository {
+    pub fn new(region: &Region, table: &str, location_index: &str) -> Self {
+        DynamoDbEventsRepository{
+            client: dynamodb_util::client(region),
+            table: table.to_owned(),
+            location_index: location_index.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventsRepository for DynamoDbEventsRepository {
+    async fn get_by_date(&self, date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        let mut attribute_values = HashMap::new();
+        attribute_values.insert(":date".to_owned(), AttributeValue{
+            s: Some(date.format(iso_date::FORMAT).to_string()),
+            ..Default::default()
+        });
+        let items: Vec<Item> = dynamodb_util::query(&self.client, QueryInput{
+                table_name: self.table.clone(),
+                expression_attribute_values: Some(attribute_values),
+                key_condition_expression: Some("event_date = :date".to_owned()),
                 ..Default::default()
-            });
-            if let true = event.description.is_some() {
-                attributes.insert("description".to_owned(), AttributeValue{
-                    s: Some(event.description.unwrap()),
-                    ..Default::default()
-                });
-            }
-            attributes.insert("start_time".to_owned(), AttributeValue{
-                s: Some(event.time_start),
+            })
+            .try_collect()
+            .await?;
+        let mut events = items.iter()
+            .map(item_to_event)
+            .collect::<Result<Vec<PickUpEvent>, MalformedEvent>>()?;
+        events.sort();
+        Ok(events)
+    }
+
+    async fn get_from_date(&self, from_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        let mut attribute_values = HashMap::new();
+        attribute_values.insert(":fromDate".to_owned(), AttributeValue{
+            s: Some(from_date.format(iso_date::FORMAT).to_string()),
+            ..Default::default()
+        });
+        let items: Vec<Item> = dynamodb_util::scan(&self.client, ScanInput{
+                table_name: self.table.clone(),
+                expression_attribute_values: Some(attribute_values),
+                filter_expression: Some("event_date >= :fromDate".to_owned()),
                 ..Default::default()
-            });
-            attributes.insert("end_time".to_owned(), AttributeValue{
-                s: Some(event.time_end),
+            })
+            .try_collect()
+            .await?;
+        Ok(items.iter()
+            .map(item_to_event)
+            .collect::<Result<Vec<PickUpEvent>, MalformedEvent>>()?)
+    }
+
+    async fn get_by_location(&self, location_id: &str, from_date: NaiveDate, to_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        let mut attribute_values = HashMap::new();
+        attribute_values.insert(":locationId".to_owned(), AttributeValue{
+            s: Some(location_id.to_owned()),
+            ..Default::default()
+        });
+        attribute_values.insert(":fromDate".to_owned(), AttributeValue{
+            s: Some(from_date.format(iso_date::FORMAT).to_string()),
+            ..Default::default()
+        });
+        attribute_values.insert(":toDate".to_owned(), AttributeValue{
+            s: Some(to_date.format(iso_date::FORMAT).to_string()),
+            ..Default::default()
+        });
+        let items: Vec<Item> = dynamodb_util::query(&self.client, QueryInput{
+                table_name: self.table.clone(),
+                index_name: Some(self.location_index.clone()),
+                expression_attribute_values: Some(attribute_values),
+                key_condition_expression: Some("location_id = :locationId AND event_date BETWEEN :fromDate AND :toDate".to_owned()),
                 ..Default::default()
-            });
-            WriteRequest{
+            })
+            .try_collect()
+            .await?;
+        let mut events = items.iter()
+            .map(item_to_event)
+            .collect::<Result<Vec<PickUpEvent>, MalformedEvent>>()?;
+        events.sort();
+        Ok(events)
+    }
+
+    async fn store(&self, events: Vec<PickUpEvent>) -> Result<WriteOutcome, EventsRepoError> {
+        let write_requests: Vec<WriteRequest> = events.into_iter()
+            .map(|event| WriteRequest{
                 put_request: Some(PutRequest{
-                    item: attributes
+                    item: event_to_item(event)
                 }),
                 ..Default::default()
-            }
+            })
+            .collect();
+        write_in_batches(&self.client, &self.table, write_requests).await
+    }
+
+    async fn delete(&self, keys: &[EventKey]) -> Result<WriteOutcome, EventsRepoError> {
+        let write_requests: Vec<WriteRequest> = keys.iter()
+            .map(|key| WriteRequest{
+                delete_request: Some(DeleteRequest{
+                    key: key_attributes(key)
+                }),
+                ..Default::default()
+            })
+            .collect();
+        write_in_batches(&self.client, &self.table, write_requests).await
+    }
+}
+
+/// Keeps events in memory, for tests and local runs.
+#[derive(Default)]
+pub struct InMemoryEventsRepository {
+    events: Mutex<BTreeMap<EventKey, PickUpEvent>>,
+}
+
+impl InMemoryEventsRepository {
+    pub fn with_events(events: Vec<PickUpEvent>) -> Self {
+        InMemoryEventsRepository{
+            events: Mutex::new(events.into_iter()
+                .map(|event| (event.key(), event))
+                .collect()),
+        }
+    }
+
+    /// Returns every stored event, sorted by location and start time.
+    pub fn all(&self) -> Vec<PickUpEvent> {
+        let mut events: Vec<PickUpEvent> = self.events.lock().unwrap().values().cloned().collect();
+        events.sort();
+        events
+    }
+}
+
+#[async_trait]
+impl EventsRepository for InMemoryEventsRepository {
+    async fn get_by_date(&self, date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        Ok(self.all().into_iter()
+            .filter(|event| event.date == date)
+            .collect())
+    }
+
+    async fn get_from_date(&self, from_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        Ok(self.all().into_iter()
+            .filter(|event| event.date >= from_date)
+            .collect())
+    }
+
+    async fn get_by_location(&self, location_id: &str, from_date: NaiveDate, to_date: NaiveDate) -> Result<Vec<PickUpEvent>, Error> {
+        Ok(self.all().into_iter()
+            .filter(|event| event.location_id == location_id && event.date >= from_date && event.date <= to_date)
+            .collect())
+    }
+
+    async fn store(&self, events: Vec<PickUpEvent>) -> Result<WriteOutcome, EventsRepoError> {
+        let written = events.len();
+        let mut stored_events = self.events.lock().unwrap();
+        for event in events {
+            stored_events.insert(event.key(), event);
+        }
+        Ok(WriteOutcome{
+            written,
+            ..Default::default()
         })
-        .collect();
-    let mut batch_write_requests: Vec<BatchWriteItemInput> = Vec::new();
-    for chunk in write_requests.chunks(25) {
-        let chunk = chunk.to_vec();
+    }
+
+    async fn delete(&self, keys: &[EventKey]) -> Result<WriteOutcome, EventsRepoError> {
+        let mut stored_events = self.events.lock().unwrap();
+        for key in keys {
+            stored_events.remove(key);
+        }
+        // Like DynamoDB, deleting an event which does not exist succeeds
+        Ok(WriteOutcome{
+            written: keys.len(),
+            ..Default::default()
+        })
+    }
+}
+
+fn item_to_event(item: &HashMap<String, AttributeValue>) -> Result<PickUpEvent, MalformedEvent> {
+    let location_id = required_string(item, "location_id", "-")?;
+    let optional_string = |name: &str| match item.get(name) {
+        None => Ok(None),
+        Some(value) => match value.s.as_ref() {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(MalformedEvent{
+                message: format!("{} of {} is not a string", name, location_id)
+            })
+        }
+    };
+    let time = |name: &str| required_string(item, name, &location_id)
+        .and_then(|time| parse_time(&time).map_err(|e| MalformedEvent{
+            message: format!("{} of {}: {}", name, location_id, e)
+        }));
+    let status = match optional_string("status")? {
+        Some(status) => match EventStatus::from_str(&status) {
+            Ok(status) => status,
+            Err(e) => return Err(MalformedEvent{
+                message: format!("status of {}: {}", location_id, e)
+            })
+        },
+        None => EventStatus::Scheduled,
+    };
+    let event = PickUpEvent::new_with_id(
+        location_id.clone(),
+        required_string(item, "street", &location_id)?,
+        required_string(item, "district", &location_id)?,
+        optional_string("organisation_type")?,
+        optional_string("description")?,
+        time("start_time")?,
+        time("end_time")?,
+    );
+    match event {
+        Ok(event) => Ok(event
+            .with_status(status)
+            .with_coordinate(item_to_coordinate(item))),
+        Err(e) => Err(MalformedEvent{
+            message: e.to_string()
+        })
+    }
+}
+
+fn required_string(item: &HashMap<String, AttributeValue>, name: &str, location_id: &str) -> Result<String, MalformedEvent> {
+    match item.get(name).and_then(|value| value.s.as_ref()) {
+        Some(value) => Ok(value.clone()),
+        None => Err(MalformedEvent{
+            message: format!("{} of {} is missing", name, location_id)
+        })
+    }
+}
+
+fn event_to_item(event: PickUpEvent) -> HashMap<String, AttributeValue> {
+    let mut attributes: HashMap<String, AttributeValue> = key_attributes(&event.key());
+    attributes.insert("location_id".to_owned(), AttributeValue{
+        s: Some(event.location_id),
+        ..Default::default()
+    });
+    attributes.insert("district".to_owned(), AttributeValue{
+        s: Some(event.district),
+        ..Default::default()
+    });
+    attributes.insert("street".to_owned(), AttributeValue{
+        s: Some(event.street),
+        ..Default::default()
+    });
+    if let Some(organisation_type) = event.organisation_type {
+        attributes.insert("organisation_type".to_owned(), AttributeValue{
+            s: Some(organisation_type),
+            ..Default::default()
+        });
+    }
+    if let true = event.description.is_some() {
+        attributes.insert("description".to_owned(), AttributeValue{
+            s: Some(event.description.unwrap()),
+            ..Default::default()
+        });
+    }
+    attributes.insert("start_time".to_owned(), AttributeValue{
+        s: Some(event.time_start.to_rfc3339()),
+        ..Default::default()
+    });
+    attributes.insert("end_time".to_owned(), AttributeValue{
+        s: Some(event.time_end.to_rfc3339()),
+        ..Default::default()
+    });
+    attributes.insert("status".to_owned(), AttributeValue{
+        s: Some(event.status.to_string()),
+        ..Default::default()
+    });
+    if let Some(coordinate) = &event.coordinate {
+        insert_coordinate(&mut attributes, coordinate);
+    }
+    attributes
+}
+
+fn key_attributes(key: &EventKey) -> HashMap<String, AttributeValue> {
+    let mut attributes: HashMap<String, AttributeValue> = HashMap::new();
+    attributes.insert("event_date".to_owned(), AttributeValue{
+        s: Some(key.date.format(iso_date::FORMAT).to_string()),
+        ..Default::default()
+    });
+    attributes.insert("event_id".to_owned(), AttributeValue{
+        s: Some(key.event_id.clone()),
+        ..Default::default()
+    });
+    attributes
+}
+
+fn request_key(request: &WriteRequest) -> Option<EventKey> {
+    let attributes = match (&request.put_request, &request.delete_request) {
+        (Some(put_request), _) => &put_request.item,
+        (None, Some(delete_request)) => &delete_request.key,
+        (None, None) => return None,
+    };
+    let date = attributes.get("event_date").and_then(|value| value.s.as_ref())?;
+    let event_id = attributes.get("event_id").and_then(|value| value.s.as_ref())?;
+    Some(EventKey{
+        date: NaiveDate::parse_from_str(date, iso_date::FORMAT).ok()?,
+        event_id: event_id.clone(),
+    })
+}
+
+/// Writes to any table in batches, retrying unprocessed writes until the deadline of the retry policy.
+pub(crate) async fn write_in_batches(client: &DynamoDbClient, table: &str, write_requests: Vec<WriteRequest>) -> Result<WriteOutcome, EventsRepoError> {
+    let write_batch = |batch: Vec<WriteRequest>| async move {
         let mut request_items: HashMap<String, Vec<WriteRequest>> = HashMap::new();
-        request_items.insert(table.clone(), chunk);
-        batch_write_requests.push(BatchWriteItemInput{
+        request_items.insert(table.to_owned(), batch);
+        let output = client.batch_write_item(BatchWriteItemInput{
             request_items,
             ..Default::default()
-        });
+        }).await?;
+        Ok(output.unprocessed_items
+            .and_then(|mut unprocessed_items| unprocessed_items.remove(table))
+            .unwrap_or_else(Vec::new))
+    };
+    write_with_retry(write_batch, write_requests, &RETRY_POLICY).await
+}
+
+/// Sends the writes in concurrent batches. `write_batch` returns the writes which DynamoDB left unprocessed,
+/// which are retried with exponential backoff until the deadline of the policy.
+async fn write_with_retry<F, Fut>(write_batch: F, write_requests: Vec<WriteRequest>, policy: &RetryPolicy) -> Result<WriteOutcome, EventsRepoError>
+where
+    F: Fn(Vec<WriteRequest>) -> Fut,
+    Fut: Future<Output = Result<Vec<WriteRequest>, RusotoError<BatchWriteItemError>>>,
+{
+    let deadline = Instant::now() + policy.deadline;
+    let batches: Vec<Vec<WriteRequest>> = write_requests.chunks(BATCH_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let batch_outcomes: Vec<(WriteOutcome, Option<RusotoError<BatchWriteItemError>>)> = stream::iter(batches)
+        .map(|batch| write_batch_with_retry(&write_batch, batch, policy, deadline))
+        .buffer_unordered(CONCURRENT_BATCHES)
+        .collect()
+        .await;
+    let mut outcome = WriteOutcome::default();
+    let mut errors: Vec<RusotoError<BatchWriteItemError>> = Vec::new();
+    let mut failed_in_errors: Vec<EventKey> = Vec::new();
+    for (batch_outcome, error) in batch_outcomes {
+        outcome.written += batch_outcome.written;
+        outcome.retried += batch_outcome.retried;
+        match error {
+            Some(e) => {
+                errors.push(e);
+                failed_in_errors.extend(batch_outcome.failed);
+            },
+            None => outcome.failed.extend(batch_outcome.failed),
+        }
     }
-    let mut db_errors: Vec<RusotoError<BatchWriteItemError>> = Vec::new(); 
-    let mut unprocessed_count = 0_usize;
-    for batch_write_request in batch_write_requests {
-        match client.batch_write_item(batch_write_request).await {
-            Ok(output) => if let Some(unprocessed_items) = output.unprocessed_items {
-                if let Some(items_for_table) = unprocessed_items.get(&table) {
-                    unprocessed_count += items_for_table.len(); // TODO: Do this in a less nested way..
-                }
+    if errors.is_empty() {
+        outcome.failed.sort();
+        return Ok(outcome);
+    }
+    failed_in_errors.extend(outcome.failed);
+    failed_in_errors.sort();
+    Err(EventsRepoError{
+        errors,
+        failed: failed_in_errors,
+    })
+}
+
+async fn write_batch_with_retry<F, Fut>(write_batch: &F, batch: Vec<WriteRequest>, policy: &RetryPolicy, deadline: Instant) -> (WriteOutcome, Option<RusotoError<BatchWriteItemError>>)
+where
+    F: Fn(Vec<WriteRequest>) -> Fut,
+    Fut: Future<Output = Result<Vec<WriteRequest>, RusotoError<BatchWriteItemError>>>,
+{
+    let mut outcome = WriteOutcome::default();
+    let mut pending = batch;
+    let mut backoff = policy.initial_backoff;
+    loop {
+        let pending_count = pending.len();
+        match write_batch(pending.clone()).await {
+            Ok(unprocessed) => {
+                outcome.written += pending_count - unprocessed.len();
+                pending = unprocessed;
             },
+            // A throttled request is retried like a batch where nothing was processed
+            Err(RusotoError::Service(BatchWriteItemError::ProvisionedThroughputExceeded(_))) |
+            Err(RusotoError::Service(BatchWriteItemError::RequestLimitExceeded(_))) => {},
             Err(e) => {
-                db_errors.push(e);
-            },
+                outcome.failed = pending.iter().filter_map(request_key).collect();
+                return (outcome, Some(e));
+            }
+        }
+        if pending.is_empty() {
+            return (outcome, None);
+        }
+        let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+        let delay = backoff + Duration::from_millis(jitter);
+        if Instant::now() + delay > deadline {
+            warn!("Giving up on {} unprocessed writes at the deadline", pending.len());
+            outcome.failed = pending.iter().filter_map(request_key).collect();
+            return (outcome, None);
+        }
+        time::delay_for(delay).await;
+        outcome.retried += pending.len();
+        backoff = min(backoff * 2, policy.max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(attributes: &[(&str, &str)]) -> HashMap<String, AttributeValue> {
+        attributes.iter()
+            .map(|(name, value)| (name.to_string(), AttributeValue{
+                s: Some(value.to_string()),
+                ..Default::default()
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn should_read_event() {
+        let event = item_to_event(&item(&[
+            ("location_id", "farligt-avfall:centrum_storgatan"),
+            ("street", "Storgatan"),
+            ("district", "Centrum"),
+            ("start_time", "2020-09-23T16:00:00+00:00"),
+            ("end_time", "2020-09-23T16:45:00+00:00"),
+            ("status", "cancelled"),
+        ])).unwrap();
+        assert_eq!(parse_time("2020-09-23T18:00:00+02:00").unwrap(), event.time_start);
+        assert_eq!(EventStatus::Cancelled, event.status);
+        assert_eq!(None, event.description);
+    }
+
+    #[test]
+    fn should_fail_on_missing_attribute() {
+        let result = item_to_event(&item(&[
+            ("location_id", "farligt-avfall:centrum_storgatan"),
+            ("district", "Centrum"),
+            ("start_time", "2020-09-23T16:00:00+00:00"),
+            ("end_time", "2020-09-23T16:45:00+00:00"),
+        ]));
+        assert!(result.unwrap_err().message.contains("street"));
+    }
+
+    #[test]
+    fn should_fail_on_malformed_time() {
+        let result = item_to_event(&item(&[
+            ("location_id", "farligt-avfall:centrum_storgatan"),
+            ("street", "Storgatan"),
+            ("district", "Centrum"),
+            ("start_time", "23/9 18:00"),
+            ("end_time", "2020-09-23T16:45:00+00:00"),
+        ]));
+        assert!(result.unwrap_err().message.contains("start_time"));
+    }
+
+    fn put_request(event_id: &str) -> WriteRequest {
+        WriteRequest{
+            put_request: Some(PutRequest{
+                item: key_attributes(&EventKey{
+                    date: NaiveDate::from_ymd(2020, 9, 23),
+                    event_id: event_id.to_owned(),
+                })
+            }),
+            ..Default::default()
+        }
+    }
+
+    const TEST_POLICY: RetryPolicy = RetryPolicy{
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(4),
+        deadline: Duration::from_millis(500),
+    };
+
+    #[tokio::test]
+    async fn should_retry_unprocessed_writes() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let write_batch = |batch: Vec<WriteRequest>| {
+            let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                // The first attempt leaves the last two writes unprocessed
+                match call {
+                    0 => Ok(batch[batch.len() - 2..].to_vec()),
+                    _ => Ok(Vec::new()),
+                }
+            }
+        };
+        let requests = vec![put_request("a"), put_request("b"), put_request("c")];
+        let outcome = write_with_retry(write_batch, requests, &TEST_POLICY).await.unwrap();
+        assert_eq!(WriteOutcome{ written: 3, retried: 2, failed: vec![] }, outcome);
+    }
+
+    #[tokio::test]
+    async fn should_write_in_batches_of_25() {
+        let write_batch = |batch: Vec<WriteRequest>| async move {
+            assert!(batch.len() <= BATCH_SIZE);
+            Ok(Vec::new())
+        };
+        let requests: Vec<WriteRequest> = (0..60).map(|i| put_request(&i.to_string())).collect();
+        let outcome = write_with_retry(write_batch, requests, &TEST_POLICY).await.unwrap();
+        assert_eq!(60, outcome.written);
+    }
+
+    #[tokio::test]
+    async fn should_report_writes_unprocessed_at_deadline() {
+        let write_batch = |batch: Vec<WriteRequest>| async move {
+            match batch.len() {
+                1 => Err(RusotoError::Service(BatchWriteItemError::ProvisionedThroughputExceeded("Slow down".to_owned()))),
+                _ => Ok(batch[1..].to_vec()),
+            }
+        };
+        let requests = vec![put_request("a"), put_request("b")];
+        let outcome = write_with_retry(write_batch, requests, &TEST_POLICY).await.unwrap();
+        assert_eq!(1, outcome.written);
+        assert_eq!(vec!["b".to_owned()], outcome.failed.into_iter().map(|key| key.event_id).collect::<Vec<String>>());
+    }
+
+    #[tokio::test]
+    async fn should_return_error_when_batch_fails() {
+        let write_batch = |_batch: Vec<WriteRequest>| async move {
+            Err(RusotoError::Validation("Bad item".to_owned()))
         };
+        let requests = vec![put_request("a"), put_request("b")];
+        let error = write_with_retry(write_batch, requests, &TEST_POLICY).await.unwrap_err();
+        assert_eq!(1, error.errors.len());
+        assert_eq!(2, error.failed.len());
+    }
+
+    #[tokio::test]
+    async fn should_keep_several_visits_on_the_same_day_in_memory() {
+        let visit = |start: &str, end: &str| PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap();
+        let events = InMemoryEventsRepository::default();
+        events.store(vec![
+            visit("2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00"),
+            visit("2020-09-23T08:00:00+00:00", "2020-09-23T08:45:00+00:00"),
+        ]).await.unwrap();
+        let stored = events.get_by_date(NaiveDate::from_ymd(2020, 9, 23)).await.unwrap();
+        assert_eq!(2, stored.len());
+        assert!(stored[0].time_start < stored[1].time_start);
+
+        events.delete(&[stored[0].key()]).await.unwrap();
+        assert_eq!(vec![stored[1].clone()], events.get_from_date(NaiveDate::from_ymd(2020, 9, 23)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_get_events_of_location_between_dates() {
+        let visit = |street: &str, start: &str, end: &str| PickUpEvent::new("farligt-avfall", street.to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap();
+        let events = InMemoryEventsRepository::with_events(vec![
+            visit("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00"),
+            visit("Storgatan", "2020-10-23T16:00:00+00:00", "2020-10-23T16:45:00+00:00"),
+            visit("Storgatan", "2020-11-23T16:00:00+00:00", "2020-11-23T16:45:00+00:00"),
+            visit("Kungsgatan", "2020-10-23T08:00:00+00:00", "2020-10-23T08:45:00+00:00"),
+        ]);
+        let found = events.get_by_location("farligt-avfall:centrum_storgatan", NaiveDate::from_ymd(2020, 9, 24), NaiveDate::from_ymd(2020, 11, 23)).await.unwrap();
+        assert_eq!(vec![NaiveDate::from_ymd(2020, 10, 23), NaiveDate::from_ymd(2020, 11, 23)], found.iter().map(|event| event.date).collect::<Vec<NaiveDate>>());
     }
-    Ok(unprocessed_count)
 }
@@ -1,4 +1,6 @@
 pub mod pickup_event;
+pub mod city_profile;
+pub mod time_format;
 pub mod pickup_stop;
 pub mod coordinate;
 pub mod events_repo;
@@ -7,3 +9,4 @@ pub mod subscription;
 pub mod subscriptions_repo;
 pub mod stops_repo;
 pub mod dynamodb_util;
+pub mod scraper;
@@ -1,33 +1,186 @@
-use std::{fmt, error, cmp::Ordering};
+use std::{fmt, error, cmp::Ordering, str::FromStr};
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::coordinate::Coordinate;
+use crate::time_format::{rfc3339, iso_date};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
 
-#[derive(fmt::Debug, Serialize, Deserialize)]
+/// The Farligt Avfall-bilen. Location ids stored before they were namespaced by service have no prefix and belong to this service.
+pub const FARLIGT_AVFALL_SERVICE_ID: &str = "farligt-avfall";
+
+const SERVICE_SEPARATOR: char = ':';
+
+/// Returns the service which a location id belongs to.
+pub fn service_of(location_id: &str) -> &str {
+    match location_id.find(SERVICE_SEPARATOR) {
+        Some(index) => &location_id[..index],
+        None => FARLIGT_AVFALL_SERVICE_ID,
+    }
+}
+
+/// Returns the id which a location had before ids were namespaced by service, if it has one.
+/// Subscriptions made back then still refer to it.
+pub fn legacy_location_id(location_id: &str) -> Option<&str> {
+    let prefix_length = FARLIGT_AVFALL_SERVICE_ID.len() + SERVICE_SEPARATOR.len_utf8();
+    match service_of(location_id) == FARLIGT_AVFALL_SERVICE_ID && location_id.len() > prefix_length {
+        true => Some(&location_id[prefix_length..]),
+        false => None,
+    }
+}
+
+#[derive(fmt::Debug)]
+pub struct PickUpEventError {
+    pub message: String,
+}
+impl fmt::Display for PickUpEventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for PickUpEventError {}
+
+/// Parses a time in RFC 3339, with any offset.
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>, PickUpEventError> {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => Ok(time.with_timezone(&Utc)),
+        Err(e) => Err(PickUpEventError{
+            message: format!("Malformed time {}: {}", time, e)
+        })
+    }
+}
+
+/// Folds a district or street name into one part of a location id. The name is decomposed, stripped of diacritics and lowercased,
+/// and each run of characters other than letters and digits becomes a single dash. Differences in case, spacing, punctuation
+/// or in how an "ö" is encoded therefore give the same id.
+pub fn canonical_segment(name: &str) -> String {
+    let mut segment = String::with_capacity(name.len());
+    let mut separated = false;
+    for c in name.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+        if !c.is_alphanumeric() {
+            separated = true;
+            continue;
+        }
+        if separated && !segment.is_empty() {
+            segment.push('-');
+        }
+        separated = false;
+        segment.push(c);
+    }
+    segment
+}
+
+pub fn canonical_location_id(service_id: &str, district: &str, street: &str) -> String {
+    format!("{}{}{}_{}", service_id, SERVICE_SEPARATOR, canonical_segment(district), canonical_segment(street))
+}
+
+/// Returns the ids which a location had before they were canonical, both with and without the service prefix.
+/// Subscriptions made back then still refer to them, so they are kept as aliases of the canonical id.
+pub fn previous_location_ids(service_id: &str, district: &str, street: &str) -> Vec<String> {
+    let canonical = canonical_location_id(service_id, district, street);
+    let previous = format!("{}{}{}_{}",
+        service_id,
+        SERVICE_SEPARATOR,
+        district.to_lowercase().trim().replace(" ", "").replace("/", "-"),
+        street.to_lowercase().trim().replace(" ", "").replace("/", "-")
+    );
+    let mut ids: Vec<String> = legacy_location_id(&previous).map(|id| id.to_owned()).into_iter().collect();
+    ids.push(previous);
+    ids.retain(|id| *id != canonical);
+    ids
+}
+
+/// Whether a visit takes place as planned. A moved visit replaces an earlier date or time, which in turn is cancelled.
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventStatus {
+    #[default]
+    Scheduled,
+    Cancelled,
+    Moved,
+}
+
+impl fmt::Display for EventStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            EventStatus::Scheduled => "scheduled",
+            EventStatus::Cancelled => "cancelled",
+            EventStatus::Moved => "moved",
+        })
+    }
+}
+
+impl FromStr for EventStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scheduled" => Ok(EventStatus::Scheduled),
+            "cancelled" => Ok(EventStatus::Cancelled),
+            "moved" => Ok(EventStatus::Moved),
+            other => Err(format!("Unknown event status: {}", other)),
+        }
+    }
+}
+
+const EVENT_ID_SEPARATOR: char = '#';
+
+/// Identifies a visit among the events of the same day, e.g. "farligt-avfall:centrum_storgatan#2020-09-23T16:00:00+00:00".
+pub fn event_id(location_id: &str, time_start: &DateTime<Utc>) -> String {
+    format!("{}{}{}", location_id, EVENT_ID_SEPARATOR, time_start.to_rfc3339())
+}
+
+/// How an event is keyed in the events table.
+#[derive(fmt::Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventKey {
+    pub date: NaiveDate,
+    pub event_id: String,
+}
+
+/// Maps an id which a location used to have, e.g. before the city changed the spelling of a street, to its current id.
+#[derive(fmt::Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LocationAlias {
+    pub alias_id: String,
+    pub location_id: String,
+}
+
+#[derive(fmt::Debug, Clone, Serialize, Deserialize)]
 pub struct PickUpEvent {
+    pub service_id: String,
     pub location_id: String,
     pub street: String,
     pub district: String,
+    pub organisation_type: Option<String>,
     pub description: Option<String>,
-    pub time_start: String,
-    pub time_end: String,
-    pub date: String,
+    #[serde(with = "rfc3339")]
+    pub time_start: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub time_end: DateTime<Utc>,
+    /// The date of `time_start` in UTC, which is how events are keyed in the events table.
+    #[serde(with = "iso_date")]
+    pub date: NaiveDate,
+    #[serde(default)]
+    pub status: EventStatus,
+    /// Where the stop is, if it has been geocoded.
+    #[serde(default)]
+    pub coordinate: Option<Coordinate>,
 }
 
 impl fmt::Display for PickUpEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} - {} ({}): {} to {}", self.district, self.street, self.description.as_ref().unwrap_or(&"-".to_owned()), self.time_start, self.time_end)
+        write!(f, "{} - {} ({}): {} to {}", self.district, self.street, self.description.as_ref().unwrap_or(&"-".to_owned()), self.time_start.to_rfc3339(), self.time_end.to_rfc3339())
     }
 }
 
+/// A stop can be visited more than once a day, so an event is identified by its location and start time.
 impl Ord for PickUpEvent {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.location_id.cmp(&other.location_id)
+        (&self.location_id, self.time_start).cmp(&(&other.location_id, other.time_start))
     }
 }
 
 impl PartialEq for PickUpEvent {
     fn eq(&self, other: &Self) -> bool {
-        self.location_id == other.location_id
+        self.location_id == other.location_id && self.time_start == other.time_start
     }
 }
 impl Eq for PickUpEvent {}
@@ -39,29 +192,66 @@ impl PartialOrd for PickUpEvent {
 }
 
 impl PickUpEvent {
-    pub fn new(street: String, district: String, description: Option<String>, time_start: String, time_end: String) -> Result<Self, Box<dyn error::Error>> {
-        let location_id = format!("{}_{}",
-            district.to_lowercase().trim().replace(" ", "").replace("/", "-"),
-            street.to_lowercase().trim().replace(" ", "").replace("/", "-")
-        );
-        PickUpEvent::new_with_id(location_id, street, district, description, time_start, time_end)
+    pub fn new(service_id: &str, street: String, district: String, organisation_type: Option<String>, description: Option<String>, time_start: DateTime<Utc>, time_end: DateTime<Utc>) -> Result<Self, PickUpEventError> {
+        let location_id = canonical_location_id(service_id, &district, &street);
+        PickUpEvent::new_with_id(location_id, street, district, organisation_type, description, time_start, time_end)
     }
     
-    pub fn new_with_id(location_id: String, street: String, district: String, description: Option<String>, time_start: String, time_end: String) -> Result<Self, Box<dyn error::Error>> {
-        let time_start = DateTime::parse_from_rfc3339(&time_start)?
-            .with_timezone(&Utc);
-        let time_end = DateTime::parse_from_rfc3339(&time_end)?
-            .with_timezone(&Utc);
+    pub fn new_with_id(location_id: String, street: String, district: String, organisation_type: Option<String>, description: Option<String>, time_start: DateTime<Utc>, time_end: DateTime<Utc>) -> Result<Self, PickUpEventError> {
+        if time_end <= time_start {
+            return Err(PickUpEventError{
+                message: format!("{} ends at {}, which is not after it starts at {}", location_id, time_end.to_rfc3339(), time_start.to_rfc3339())
+            });
+        }
         Ok(PickUpEvent{
+            service_id: service_of(&location_id).to_owned(),
             location_id,
             street,
             district,
+            organisation_type,
             description,
-            time_start: time_start.to_rfc3339(),
-            time_end: time_end.to_rfc3339(),
-            date: time_start.format("%Y-%m-%d").to_string(),
+            time_start,
+            time_end,
+            date: time_start.date().naive_utc(),
+            status: EventStatus::Scheduled,
+            coordinate: None,
         })
     }
+
+    pub fn event_id(&self) -> String {
+        event_id(&self.location_id, &self.time_start)
+    }
+
+    pub fn key(&self) -> EventKey {
+        EventKey{
+            date: self.date,
+            event_id: self.event_id(),
+        }
+    }
+
+    /// The previous ids of this event's location, mapped to its current one.
+    pub fn aliases(&self) -> Vec<LocationAlias> {
+        previous_location_ids(&self.service_id, &self.district, &self.street).into_iter()
+            .map(|alias_id| LocationAlias{
+                alias_id,
+                location_id: self.location_id.clone(),
+            })
+            .collect()
+    }
+
+    pub fn with_status(self, status: EventStatus) -> Self {
+        PickUpEvent{
+            status,
+            ..self
+        }
+    }
+
+    pub fn with_coordinate(self, coordinate: Option<Coordinate>) -> Self {
+        PickUpEvent{
+            coordinate,
+            ..self
+        }
+    }
 }
 
 #[cfg(test)]
@@ -70,19 +260,89 @@ mod tests {
 
     #[test]
     fn should_generate_location_id() {
-        let event = PickUpEvent::new("  Doktor Fries torg, Doktor Bondesons Gata ".to_owned(), "Centrum".to_owned(), Some("jättestensskolan".to_owned()), "2020-09-23T18:00:00+02:00".to_owned(), "2020-09-23T18:45:00+02:00".to_owned()).unwrap();
-        assert_eq!("centrum_doktorfriestorg,doktorbondesonsgata", event.location_id);
+        let event = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "  Doktor Fries torg, Doktor Bondesons Gata ".to_owned(), "Centrum".to_owned(), Some("Kommunal".to_owned()), Some("jättestensskolan".to_owned()), parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_eq!("farligt-avfall:centrum_doktor-fries-torg-doktor-bondesons-gata", event.location_id);
+    }
+
+    #[test]
+    fn should_normalise_location_id() {
+        let composed = canonical_location_id(FARLIGT_AVFALL_SERVICE_ID, "Västra Frölunda", "Önnereds  Brygga");
+        let decomposed = canonical_location_id(FARLIGT_AVFALL_SERVICE_ID, "va\u{308}stra fro\u{308}lunda", "O\u{308}nnereds brygga ");
+        assert_eq!("farligt-avfall:vastra-frolunda_onnereds-brygga", composed);
+        assert_eq!(composed, decomposed);
+        assert_eq!(canonical_segment("Doktor Fries torg - Doktor Bondesons gata"), canonical_segment("Doktor Fries torg, Doktor Bondesons Gata"));
+    }
+
+    #[test]
+    fn should_alias_previous_location_ids() {
+        let event = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Doktor Fries torg".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_eq!(vec![
+            LocationAlias{alias_id: "centrum_doktorfriestorg".to_owned(), location_id: "farligt-avfall:centrum_doktor-fries-torg".to_owned()},
+            LocationAlias{alias_id: "farligt-avfall:centrum_doktorfriestorg".to_owned(), location_id: "farligt-avfall:centrum_doktor-fries-torg".to_owned()},
+        ], event.aliases());
+    }
+
+    #[test]
+    fn should_not_alias_unchanged_location_id() {
+        assert!(previous_location_ids("miljobilen", "Centrum", "Storgatan").is_empty());
     }
 
     #[test]
     fn should_not_include_slash_in_id() {
-        let event = PickUpEvent::new("Utmarksgatan/Dysiksgatan".to_owned(), "Lundby".to_owned(), None, "2020-09-23T18:00:00+02:00".to_owned(), "2020-09-23T18:45:00+02:00".to_owned()).unwrap();
-        assert_eq!("lundby_utmarksgatan-dysiksgatan", event.location_id);
+        let event = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Utmarksgatan/Dysiksgatan".to_owned(), "Lundby".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_eq!("farligt-avfall:lundby_utmarksgatan-dysiksgatan", event.location_id);
     }
 
     #[test]
     fn should_generate_date() {
-        let event = PickUpEvent::new("Utmarksgatan/Dysiksgatan".to_owned(), "Lundby".to_owned(), None, "2020-09-23T18:00:00+02:00".to_owned(), "2020-09-23T18:45:00+02:00".to_owned()).unwrap();
-        assert_eq!("2020-09-23".to_owned(), event.date);
+        let event = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Utmarksgatan/Dysiksgatan".to_owned(), "Lundby".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_eq!(NaiveDate::from_ymd(2020, 9, 23), event.date);
+    }
+
+    #[test]
+    fn should_namespace_location_id_by_service() {
+        let event = PickUpEvent::new("miljobilen", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_eq!("miljobilen:centrum_storgatan", event.location_id);
+        assert_eq!("miljobilen", event.service_id);
+        assert_eq!(None, legacy_location_id(&event.location_id));
+    }
+
+    #[test]
+    fn should_default_to_scheduled_status() {
+        let event: PickUpEvent = serde_json::from_str(r#"{"service_id": "farligt-avfall", "location_id": "farligt-avfall:centrum_storgatan", "street": "Storgatan", "district": "Centrum", "organisation_type": null, "description": null, "time_start": "2020-09-23T16:00:00+00:00", "time_end": "2020-09-23T16:45:00+00:00", "date": "2020-09-23"}"#).unwrap();
+        assert_eq!(EventStatus::Scheduled, event.status);
+        assert_eq!(Ok(EventStatus::Cancelled), EventStatus::from_str(&EventStatus::Cancelled.to_string()));
+    }
+
+    #[test]
+    fn should_treat_unprefixed_ids_as_farligt_avfall() {
+        assert_eq!(FARLIGT_AVFALL_SERVICE_ID, service_of("centrum_storgatan"));
+        assert_eq!(Some("centrum_storgatan"), legacy_location_id("farligt-avfall:centrum_storgatan"));
+    }
+
+    #[test]
+    fn should_reject_end_before_start() {
+        let result = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T18:45:00+02:00").unwrap(), parse_time("2020-09-23T18:00:00+02:00").unwrap());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_keep_wire_format_of_times() {
+        let event = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!("2020-09-23T16:00:00+00:00", json["time_start"]);
+        assert_eq!("2020-09-23T16:45:00+00:00", json["time_end"]);
+        assert_eq!("2020-09-23", json["date"]);
+    }
+
+    #[test]
+    fn should_tell_visits_on_the_same_day_apart() {
+        let morning = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T10:00:00+02:00").unwrap(), parse_time("2020-09-23T10:45:00+02:00").unwrap()).unwrap();
+        let evening = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap();
+        assert_ne!(morning, evening);
+        assert!(morning < evening);
+        assert_eq!(morning.date, evening.date);
+        assert_eq!("farligt-avfall:centrum_storgatan#2020-09-23T08:00:00+00:00", morning.event_id());
+        assert_ne!(morning.key(), evening.key());
     }
 }
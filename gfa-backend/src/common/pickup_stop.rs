@@ -1,12 +1,17 @@
-use std::fmt;
+use std::{fmt, cmp::Ordering};
 use serde::{Serialize, Deserialize};
+use crate::pickup_event::service_of;
+use crate::coordinate::Coordinate;
 
-#[derive(fmt::Debug, Serialize, Deserialize, PartialOrd, Ord)]
+#[derive(fmt::Debug, Clone, Serialize, Deserialize)]
 pub struct PickUpStop {
+    pub service_id: String,
     pub location_id: String,
     pub street: String,
     pub district: String,
+    pub organisation_type: Option<String>,
     pub description: Option<String>,
+    pub coordinate: Option<Coordinate>,
 }
 
 impl fmt::Display for PickUpStop {
@@ -29,13 +34,35 @@ impl PartialEq for PickUpStop {
 }
 impl Eq for PickUpStop {}
 
+impl Ord for PickUpStop {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.location_id.cmp(&other.location_id)
+    }
+}
+
+impl PartialOrd for PickUpStop {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl PickUpStop {
-    pub fn new(location_id: String, street: String, district: String, description: Option<String>) -> Self {
+    pub fn new(location_id: String, street: String, district: String, organisation_type: Option<String>, description: Option<String>) -> Self {
         PickUpStop{
+            service_id: service_of(&location_id).to_owned(),
             location_id,
             street,
             district,
+            organisation_type,
             description,
+            coordinate: None,
+        }
+    }
+
+    pub fn with_coordinate(self, coordinate: Option<Coordinate>) -> Self {
+        PickUpStop{
+            coordinate,
+            ..self
         }
     }
 }
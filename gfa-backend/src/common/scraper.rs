@@ -0,0 +1,21 @@
+//! Fetching and parsing of the schedule pages, shared by the scraper lambda and the gfa-scrape command.
+pub mod calendar_words;
+pub mod page_fetcher;
+pub mod page_fingerprint;
+pub mod page_parser;
+pub mod page_source;
+pub mod schedule_grammar;
+pub mod schedule_source;
+
+use std::path::PathBuf;
+
+/// The recorded schedule pages which tests replay. It is not limited to `cfg(test)`, since the tests of the scraper lambda
+/// and gfa-scrape use it too.
+pub fn test_resources() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/common/scraper/resources/test"))
+}
+
+#[cfg(test)]
+pub fn read_test_resource(name: &str) -> Vec<u8> {
+    std::fs::read(test_resources().join(name)).unwrap()
+}
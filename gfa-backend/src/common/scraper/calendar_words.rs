@@ -0,0 +1,164 @@
+use chrono::Weekday;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("måndag", Weekday::Mon),
+    ("tisdag", Weekday::Tue),
+    ("onsdag", Weekday::Wed),
+    ("torsdag", Weekday::Thu),
+    ("fredag", Weekday::Fri),
+    ("lördag", Weekday::Sat),
+    ("söndag", Weekday::Sun),
+];
+
+const MONTHS: [(&str, u32); 12] = [
+    ("januari", 1),
+    ("februari", 2),
+    ("mars", 3),
+    ("april", 4),
+    ("maj", 5),
+    ("juni", 6),
+    ("juli", 7),
+    ("augusti", 8),
+    ("september", 9),
+    ("oktober", 10),
+    ("november", 11),
+    ("december", 12),
+];
+
+// Shorter words than this are too ambiguous to be treated as abbreviations or typos.
+const MIN_WORD_LENGTH: usize = 3;
+const MIN_CONFIDENCE: f64 = 0.75;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recognized<T> {
+    pub value: T,
+    pub canonical: &'static str,
+    /// 1.0 for full names and abbreviations, lower the more edits were needed to reach the canonical name.
+    pub confidence: f64,
+}
+
+impl<T> Recognized<T> {
+    pub fn is_exact(&self) -> bool {
+        (self.confidence - 1.0).abs() < f64::EPSILON
+    }
+}
+
+pub fn recognize_weekday(word: &str) -> Option<Recognized<Weekday>> {
+    recognize(word, &WEEKDAYS)
+}
+
+pub fn recognize_month(word: &str) -> Option<Recognized<u32>> {
+    recognize(word, &MONTHS)
+}
+
+fn recognize<T: Copy + PartialEq>(word: &str, words: &[(&'static str, T)]) -> Option<Recognized<T>> {
+    let word = word.trim().trim_end_matches('.').to_lowercase();
+    let length = word.chars().count();
+    if let Some((canonical, value)) = words.iter().find(|(canonical, _)| *canonical == word) {
+        return Some(Recognized{ value: *value, canonical, confidence: 1.0 });
+    }
+    if length < MIN_WORD_LENGTH {
+        return None;
+    }
+    let prefixed: Vec<&(&'static str, T)> = words.iter()
+        .filter(|(canonical, _)| canonical.starts_with(&word))
+        .collect();
+    if prefixed.len() == 1 {
+        let (canonical, value) = prefixed[0];
+        return Some(Recognized{ value: *value, canonical, confidence: 1.0 });
+    }
+    let mut scored: Vec<(f64, &(&'static str, T))> = words.iter()
+        .map(|entry| {
+            let longest = length.max(entry.0.chars().count());
+            (1.0 - edit_distance(&word, entry.0) as f64 / longest as f64, entry)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    let (confidence, (canonical, value)) = scored[0];
+    if confidence < MIN_CONFIDENCE {
+        return None;
+    }
+    let ambiguous = scored.iter()
+        .skip(1)
+        .any(|(other_confidence, (_, other_value))| (other_confidence - confidence).abs() < f64::EPSILON && other_value != value);
+    if ambiguous {
+        return None;
+    }
+    Some(Recognized{ value: *value, canonical, confidence })
+}
+
+/// Optimal string alignment distance, which is Levenshtein distance that also counts a swap of two adjacent characters as a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_recognize_full_names() {
+        let recognized = recognize_weekday("Tisdag").unwrap();
+        assert_eq!(Weekday::Tue, recognized.value);
+        assert!(recognized.is_exact());
+        assert_eq!(10, recognize_month("oktober").unwrap().value);
+    }
+
+    #[test]
+    fn should_recognize_abbreviations() {
+        assert_eq!(Weekday::Tue, recognize_weekday("tis").unwrap().value);
+        assert_eq!(10, recognize_month("okt.").unwrap().value);
+        assert_eq!(9, recognize_month("sept").unwrap().value);
+        assert!(recognize_month("sept").unwrap().is_exact());
+    }
+
+    #[test]
+    fn should_recognize_swapped_letters() {
+        let recognized = recognize_weekday("tisadg").unwrap();
+        assert_eq!(Weekday::Tue, recognized.value);
+        assert!(!recognized.is_exact());
+        assert_eq!(Weekday::Wed, recognize_weekday("onsadg").unwrap().value);
+        assert_eq!(9, recognize_month("sepetmber").unwrap().value);
+    }
+
+    #[test]
+    fn should_recognize_misspelled_names() {
+        assert_eq!(Weekday::Thu, recognize_weekday("tossdag").unwrap().value);
+        assert_eq!(2, recognize_month("febuari").unwrap().value);
+    }
+
+    #[test]
+    fn should_not_recognize_unrelated_words() {
+        assert_eq!(None, recognize_weekday("parkeringen"));
+        assert_eq!(None, recognize_weekday("xyzdag"));
+        assert_eq!(None, recognize_month("septerror"));
+    }
+
+    #[test]
+    fn should_not_guess_between_equally_close_names() {
+        // "jul" is an abbreviation of juli, but "jusi" is one edit away from both juni and juli
+        assert_eq!(7, recognize_month("jul").unwrap().value);
+        assert_eq!(None, recognize_month("jusi"));
+    }
+}
@@ -0,0 +1,485 @@
+use futures::{stream, StreamExt};
+use std::str::FromStr;
+use std::{fmt, error, collections::BTreeMap, time::Duration};
+use regex::Regex;
+use rand::Rng;
+use select::{document, predicate};
+use lazy_static::lazy_static;
+use log::{self, warn};
+use crate::scraper::page_source::{PageSource, ConditionalPage, Validators};
+
+pub const PAGE_SIZE: usize = 30;
+
+lazy_static! {
+    static ref PAGINATION_RE: Regex = Regex::new(r"Epagination!\d+==/").unwrap();
+}
+
+#[derive(fmt::Debug)]
+pub struct PageFetcherError {
+    pub message: String,
+    /// Whether asking again may succeed, e.g. after a timeout or a 503 Service Unavailable.
+    pub retryable: bool,
+}
+impl fmt::Display for PageFetcherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+impl error::Error for PageFetcherError {}
+
+pub struct FetchConfig {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_concurrency: usize,
+    pub timeout: Duration,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig{
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_concurrency: 3,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+pub enum FetchOutcome {
+    /// Every page fetched in the previous run answered 304 Not Modified.
+    Unchanged,
+    Pages(FetchedPages),
+}
+
+pub struct FetchedPages {
+    pub pages: Vec<Vec<u8>>,
+    /// Validators for each fetched path, to be passed to `obtain_pages_if_modified` on the next run.
+    pub validators: BTreeMap<String, Validators>,
+    /// Pages which could not be fetched, even after retries. They are left out of `pages`.
+    pub failed: Vec<FailedPage>,
+}
+
+#[derive(fmt::Debug, PartialEq)]
+pub struct FailedPage {
+    pub path: String,
+    pub message: String,
+}
+
+/// Asks for each path from the previous run with conditional requests, and only fetches all pages again if at least one of them has changed.
+/// Pagination can only be discovered from the body of the main page, so a single changed page means that everything is fetched as usual.
+pub async fn obtain_pages_if_modified<S: PageSource + Sync>(source: &S, main_path: &str, config: &FetchConfig, previous: &BTreeMap<String, Validators>) -> Result<FetchOutcome, PageFetcherError> {
+    let can_be_unchanged = !previous.is_empty() && previous.values().all(|validators| !validators.is_empty());
+    if can_be_unchanged {
+        // Owned paths keep the stream future Send, which it needs to be when fetched through a ScheduleSource
+        let results: Vec<Result<ConditionalPage, PageFetcherError>> = stream::iter(previous.clone())
+            .map(|(path, validators)| async move { fetch_with_retry(source, &path, &validators, config).await })
+            .buffered(config.max_concurrency.max(1))
+            .collect()
+            .await;
+        let mut unchanged = true;
+        for result in results {
+            match result {
+                Ok(ConditionalPage::NotModified) => (),
+                Ok(ConditionalPage::Modified(_, _)) => unchanged = false,
+                // Whether the page has changed is unknown, so everything is fetched again
+                Err(e) => {
+                    warn!("A conditional request failed, fetching all pages: {}", e);
+                    unchanged = false;
+                }
+            }
+        }
+        if unchanged {
+            return Ok(FetchOutcome::Unchanged);
+        }
+    }
+    Ok(FetchOutcome::Pages(fetch_all_pages(source, main_path, config).await?))
+}
+
+async fn fetch_all_pages<S: PageSource + Sync>(source: &S, main_path: &str, config: &FetchConfig) -> Result<FetchedPages, PageFetcherError> {
+    let mut validators: BTreeMap<String, Validators> = BTreeMap::new();
+    let (main_page, main_validators) = fetch_page(source, main_path, config).await?;
+    validators.insert(main_path.to_owned(), main_validators);
+    let total_events = find_total_items(&main_page)?;
+    let paging_path = match find_paging_path(&main_page)? {
+        Some(paging_path) => paging_path,
+        None => {
+            if total_events as usize > PAGE_SIZE {
+                return Err(PageFetcherError{
+                    message: format!("Found {} items but no pagination links, page structure has probably changed", total_events),
+                    retryable: false,
+                });
+            }
+            return Ok(FetchedPages{
+                pages: vec![main_page],
+                validators,
+                failed: Vec::new(),
+            });
+        }
+    };
+    let paths = calculate_urls(&paging_path, total_events);
+    let results: Vec<Result<(Vec<u8>, Validators), PageFetcherError>> = stream::iter(paths.clone())
+        .map(|path| async move { fetch_page(source, &path, config).await })
+        .buffered(config.max_concurrency.max(1))
+        .collect()
+        .await;
+    // A page which could not be fetched does not stop the others, the scraper decides whether enough of the schedule was fetched
+    let mut pages: Vec<Vec<u8>> = Vec::new();
+    let mut failed: Vec<FailedPage> = Vec::new();
+    for (path, result) in paths.into_iter().zip(results) {
+        match result {
+            Ok((page, page_validators)) => {
+                pages.push(page);
+                validators.insert(path, page_validators);
+            },
+            Err(e) => {
+                warn!("Could not fetch {}: {}", path, e);
+                failed.push(FailedPage{
+                    path,
+                    message: e.message,
+                });
+            }
+        };
+    }
+    Ok(FetchedPages{
+        pages,
+        validators,
+        failed,
+    })
+}
+
+async fn fetch_page<S: PageSource + Sync>(source: &S, path: &str, config: &FetchConfig) -> Result<(Vec<u8>, Validators), PageFetcherError> {
+    match fetch_with_retry(source, path, &Validators::default(), config).await? {
+        ConditionalPage::Modified(page, validators) => Ok((page, validators)),
+        ConditionalPage::NotModified => Err(PageFetcherError{
+            message: format!("Got 304 Not Modified without sending validators for: {}", path),
+            retryable: false,
+        })
+    }
+}
+
+/// Retries failed requests with exponential backoff, unless the error shows that asking again will not help. A random jitter of up
+/// to the current backoff is added to each delay, so that concurrent requests which failed at the same time do not retry in lockstep.
+async fn fetch_with_retry<S: PageSource + Sync>(source: &S, path: &str, validators: &Validators, config: &FetchConfig) -> Result<ConditionalPage, PageFetcherError> {
+    let mut backoff = config.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match source.fetch_if_modified(path, validators).await {
+            Ok(page) => return Ok(page),
+            Err(e) if !e.retryable => return Err(e),
+            Err(e) if attempt >= config.max_attempts => return Err(PageFetcherError{
+                message: format!("Giving up after {} attempts: {}", attempt, e),
+                retryable: true,
+            }),
+            Err(e) => {
+                let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+                let delay = backoff + Duration::from_millis(jitter);
+                warn!("Attempt {} of {} failed, retrying in {} ms: {}", attempt, config.max_attempts, delay.as_millis(), e);
+                tokio::time::delay_for(delay).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn find_total_items(page: &[u8]) -> Result<u16, PageFetcherError> {
+    match document::Document::from_read(page) {
+        Ok(doc) => announced_total(&doc),
+        Err(_e) => Err(PageFetcherError{
+            message: "Could not parse page".to_owned(),
+            retryable: false,
+        })
+    }
+}
+
+/// The total number of items in the schedule, from the "Hittade N" text in the result bar.
+pub fn announced_total(doc: &document::Document) -> Result<u16, PageFetcherError> {
+    lazy_static! {
+        static ref TOTAL_RE: Regex = Regex::new(r".*Hittade\s+(\d+)").unwrap();
+    }
+    let node = match doc.find(predicate::Class("c-result-bar"))
+        .into_selection()
+        .first() {
+            Some(node) => node,
+            None => return Err(PageFetcherError{
+                message: "Could not find class c-result-bar on page".to_owned(),
+                retryable: false,
+            })
+        };
+    let node = node.inner_html();
+    let captures = match TOTAL_RE.captures(&node) {
+        Some(cap) => cap,
+        None => return Err(PageFetcherError{
+            message: "Could not find pattern 'Hittade TOTAL' on page".to_owned(),
+            retryable: false,
+        })
+    };
+    let total = match captures.get(1) {
+        Some(cap) => cap.as_str(),
+        None => return Err(PageFetcherError{
+            message: "Could not find capturing group 1 when parsing total".to_owned(),
+            retryable: false,
+        })
+    };
+    match u16::from_str(total) {
+        Ok(total) => Ok(total),
+        Err(_e) => Err(PageFetcherError{
+            message: "Could not parse total as u16".to_owned(),
+            retryable: false,
+        })
+    }
+}
+
+/// Finds the path of a pagination link on the main page, to be used as a template for all other pages.
+/// Returns `None` if the page has no pagination at all, which is the case when all items fit on a single page.
+fn find_paging_path(page: &[u8]) -> Result<Option<String>, PageFetcherError> {
+    let doc = match document::Document::from_read(page) {
+        Ok(doc) => doc,
+        Err(_e) => return Err(PageFetcherError{
+            message: "Could not parse page".to_owned(),
+            retryable: false,
+        })
+    };
+    let pagination = match doc.find(predicate::Class("c-pagination"))
+        .into_selection()
+        .first() {
+            Some(node) => node,
+            None => return Ok(None)
+        };
+    let hrefs: Vec<&str> = pagination.find(predicate::Name("a"))
+        .filter_map(|link| link.attr("href"))
+        .collect();
+    if hrefs.is_empty() {
+        return Err(PageFetcherError{
+            message: "Found class c-pagination on page, but it contains no links".to_owned(),
+            retryable: false,
+        });
+    }
+    match hrefs.into_iter().find(|href| href.starts_with('/') && PAGINATION_RE.is_match(href)) {
+        Some(href) => Ok(Some(href.to_owned())),
+        None => Err(PageFetcherError{
+            message: "Could not find a pagination link matching 'Epagination!OFFSET==/' in c-pagination".to_owned(),
+            retryable: false,
+        })
+    }
+}
+
+fn calculate_urls(base_path: &str, total: u16) -> Vec::<String> {
+    let num_urls = (total as f32 / PAGE_SIZE as f32).ceil() as usize;
+    let mut urls: Vec::<String> = Vec::new();
+    for i in 0..num_urls {
+        let new_pagination = format!("Epagination!{}==/", i * PAGE_SIZE);
+        urls.push(PAGINATION_RE.replace(base_path, &new_pagination[..]).into_owned());
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use async_trait::async_trait;
+    use crate::scraper::{test_resources, read_test_resource};
+    use crate::scraper::page_source::ReplayPageSource;
+    use crate::city_profile::CityProfile;
+
+    fn main_path() -> String {
+        CityProfile::goteborg().sources[0].path.clone()
+    }
+
+    #[test]
+    fn should_find_total_items() {
+        let file = read_test_resource("body_with_items.html");
+        let total = find_total_items(&file).unwrap();
+        assert_eq!(177 as u16, total);
+    }
+
+    #[test]
+    fn should_calculate_urls() {
+        let expected_base_path = "/wps/portal/start/avfall-och-atervinning/har-lamnar-hushall-avfall/farligtavfallbilen/farligt-avfall-bilen/!ut/p/z1/04_Sj9CPykssy0xPLMnMz0vMAfIjo8ziTYzcDQy9TAy9_f1MnAwcvXxd_JwM3Y3cPcz0w8EKDFCAo4FTkJGTsYGBu7-RfhTp-pFNIk4_HgVR-I2PBOo3x6k_wEg_WD9KP6ogMT0zDxwm-pGGpgb6BbmhoRFVIY4ARalqmA!!/dz/d5/L2dBISEvZ0FBIS9nQSEh/p0/IZ7_42G01J41KON4B0AJMDNB1G2GP2=CZ6_42G01J41KON4B0AJMDNB1G2GH6=MDfilterDirection!filterOrganisationType!";
+        let expected_urls = [
+            format!("{}filterArea=Epagination!0==/", expected_base_path),
+            format!("{}filterArea=Epagination!30==/", expected_base_path),
+            format!("{}filterArea=Epagination!60==/", expected_base_path),
+            format!("{}filterArea=Epagination!90==/", expected_base_path),
+            format!("{}filterArea=Epagination!120==/", expected_base_path),
+            format!("{}filterArea=Epagination!150==/", expected_base_path)
+        ].to_vec();
+        let urls = calculate_urls(
+            &String::from("/wps/portal/start/avfall-och-atervinning/har-lamnar-hushall-avfall/farligtavfallbilen/farligt-avfall-bilen/!ut/p/z1/04_Sj9CPykssy0xPLMnMz0vMAfIjo8ziTYzcDQy9TAy9_f1MnAwcvXxd_JwM3Y3cPcz0w8EKDFCAo4FTkJGTsYGBu7-RfhTp-pFNIk4_HgVR-I2PBOo3x6k_wEg_WD9KP6ogMT0zDxwm-pGGpgb6BbmhoRFVIY4ARalqmA!!/dz/d5/L2dBISEvZ0FBIS9nQSEh/p0/IZ7_42G01J41KON4B0AJMDNB1G2GP2=CZ6_42G01J41KON4B0AJMDNB1G2GH6=MDfilterDirection!filterOrganisationType!filterArea=Epagination!0==/"),
+            177 as u16
+        );
+        assert_eq!(expected_urls, urls);
+    } 
+
+    #[test]
+    fn should_find_paging_path() {
+        let file = read_test_resource("body_with_items.html");
+        let paging_path = find_paging_path(&file).unwrap().unwrap();
+        assert!(paging_path.starts_with("/wps/portal/start/avfall-och-atervinning/har-lamnar-hushall-avfall/farligtavfallbilen/farligt-avfall-bilen/!ut/p/z1/"));
+        assert!(paging_path.ends_with("filterArea=Epagination!0==/"));
+    }
+
+    #[test]
+    fn should_not_find_paging_path_without_pagination() {
+        let page = "<html><body><div class=\"c-result-bar\"><div>Hittade 12 st</div></div></body></html>";
+        assert_eq!(None, find_paging_path(page.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn should_fail_on_unexpected_pagination_links() {
+        let page = "<html><body><div class=\"c-pagination\"><a href=\"/some/other/page?page=2\">2</a></div></body></html>";
+        assert!(find_paging_path(page.as_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_obtain_all_pages_from_source() {
+        let source = ReplayPageSource::new(test_resources()).unwrap();
+        let fetched = fetch_all_pages(&source, &main_path(), &FetchConfig::default()).await.unwrap();
+        assert_eq!(6, fetched.pages.len());
+    }
+
+    struct FlakySource {
+        failures_left: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PageSource for FlakySource {
+        async fn fetch(&self, _path: &str) -> Result<Vec<u8>, PageFetcherError> {
+            if self.failures_left.load(Ordering::SeqCst) > 0 {
+                self.failures_left.fetch_sub(1, Ordering::SeqCst);
+                return Err(PageFetcherError{
+                    message: "Connection reset".to_owned(),
+                    retryable: true,
+                });
+            }
+            Ok(b"page".to_vec())
+        }
+    }
+
+    fn quick_retries(max_attempts: u32) -> FetchConfig {
+        FetchConfig{
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            ..FetchConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_retry_failed_requests() {
+        let source = FlakySource{ failures_left: AtomicUsize::new(2) };
+        let (page, _) = fetch_page(&source, &main_path(), &quick_retries(3)).await.unwrap();
+        assert_eq!(b"page".to_vec(), page);
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_max_attempts() {
+        let source = FlakySource{ failures_left: AtomicUsize::new(3) };
+        assert!(fetch_page(&source, &main_path(), &quick_retries(3)).await.is_err());
+    }
+
+    /// Fails every request for the paths in `missing_paths` with 404 Not Found, and serves the recorded test pages otherwise.
+    struct MissingPagesSource {
+        inner: ReplayPageSource,
+        missing_paths: Vec<String>,
+        attempts: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PageSource for MissingPagesSource {
+        async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError> {
+            if self.missing_paths.iter().any(|missing| missing == path) {
+                self.attempts.fetch_add(1, Ordering::SeqCst);
+                return Err(PageFetcherError{
+                    message: "Non-OK status code: 404 Not Found".to_owned(),
+                    retryable: false,
+                });
+            }
+            self.inner.fetch(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_errors_which_will_not_go_away() {
+        let source = MissingPagesSource{
+            inner: ReplayPageSource::new(test_resources()).unwrap(),
+            missing_paths: vec![main_path()],
+            attempts: AtomicUsize::new(0),
+        };
+        assert!(fetch_page(&source, &main_path(), &quick_retries(3)).await.is_err());
+        assert_eq!(1, source.attempts.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn should_return_pages_which_could_be_fetched() {
+        let all_pages = fetch_all_pages(&conditional_source(vec![]), &main_path(), &FetchConfig::default()).await.unwrap();
+        let missing_path = all_pages.validators.keys()
+            .find(|path| path.contains("Epagination!30=="))
+            .unwrap()
+            .clone();
+        let source = MissingPagesSource{
+            inner: ReplayPageSource::new(test_resources()).unwrap(),
+            missing_paths: vec![missing_path.clone()],
+            attempts: AtomicUsize::new(0),
+        };
+        let fetched = fetch_all_pages(&source, &main_path(), &quick_retries(3)).await.unwrap();
+        assert_eq!(5, fetched.pages.len());
+        assert_eq!(1, fetched.failed.len());
+        assert_eq!(missing_path, fetched.failed[0].path);
+    }
+
+    /// Answers 304 Not Modified to conditional requests for the paths in `unchanged_paths`, and serves the recorded test pages otherwise.
+    struct ConditionalSource {
+        inner: ReplayPageSource,
+        unchanged_paths: Vec<String>,
+    }
+
+    #[async_trait]
+    impl PageSource for ConditionalSource {
+        async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError> {
+            self.inner.fetch(path).await
+        }
+
+        async fn fetch_if_modified(&self, path: &str, validators: &Validators) -> Result<ConditionalPage, PageFetcherError> {
+            if !validators.is_empty() && self.unchanged_paths.iter().any(|unchanged| unchanged == path) {
+                return Ok(ConditionalPage::NotModified);
+            }
+            Ok(ConditionalPage::Modified(self.inner.fetch(path).await?, Validators{
+                etag: Some(format!("\"{}\"", path.len())),
+                last_modified: None,
+            }))
+        }
+    }
+
+    fn conditional_source(unchanged_paths: Vec<String>) -> ConditionalSource {
+        ConditionalSource{
+            inner: ReplayPageSource::new(test_resources()).unwrap(),
+            unchanged_paths,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_report_unchanged_when_all_pages_are_not_modified() {
+        let first = match obtain_pages_if_modified(&conditional_source(vec![]), &main_path(), &FetchConfig::default(), &BTreeMap::new()).await.unwrap() {
+            FetchOutcome::Pages(fetched) => fetched,
+            FetchOutcome::Unchanged => panic!("Nothing to compare with on first run"),
+        };
+        assert_eq!(7, first.validators.len());
+        let all_paths: Vec<String> = first.validators.keys().cloned().collect();
+        let outcome = obtain_pages_if_modified(&conditional_source(all_paths), &main_path(), &FetchConfig::default(), &first.validators).await.unwrap();
+        assert!(matches!(outcome, FetchOutcome::Unchanged));
+    }
+
+    #[tokio::test]
+    async fn should_fetch_all_pages_when_one_page_is_modified() {
+        let first = match obtain_pages_if_modified(&conditional_source(vec![]), &main_path(), &FetchConfig::default(), &BTreeMap::new()).await.unwrap() {
+            FetchOutcome::Pages(fetched) => fetched,
+            FetchOutcome::Unchanged => panic!("Nothing to compare with on first run"),
+        };
+        let outcome = obtain_pages_if_modified(&conditional_source(vec![main_path()]), &main_path(), &FetchConfig::default(), &first.validators).await.unwrap();
+        match outcome {
+            FetchOutcome::Pages(fetched) => assert_eq!(6, fetched.pages.len()),
+            FetchOutcome::Unchanged => panic!("Only the main page was unchanged"),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fmt, error, collections::BTreeMap};
+use select::{document::Document, predicate};
+use serde::Serialize;
+
+#[derive(fmt::Debug)]
+pub struct LayoutChangedError {
+    pub message: String,
+}
+impl fmt::Display for LayoutChangedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Layout changed: {}", self.message)
+    }
+}
+impl error::Error for LayoutChangedError {}
+
+/// The structure of a fetched page, as far as the parser depends on it.
+#[derive(fmt::Debug, Clone, PartialEq, Serialize)]
+pub struct PageFingerprint {
+    /// How many elements have each of the classes the parser looks for.
+    pub class_counts: BTreeMap<String, usize>,
+    /// The number of items the page says the whole schedule has, if it says so.
+    pub announced_total: Option<u16>,
+}
+
+impl PageFingerprint {
+    pub fn of(doc: &Document, classes: &[&str], announced_total: Option<u16>) -> Self {
+        PageFingerprint{
+            class_counts: classes.iter()
+                .map(|class| (class.to_string(), doc.find(predicate::Class(*class)).count()))
+                .collect(),
+            announced_total,
+        }
+    }
+}
+
+/// Compares the fingerprints of all pages in a run with what the parser expects. A class which is missing from a page,
+/// or a total which differs from the number of snippets that were parsed, means that the site has most likely been redesigned.
+/// Problems within single snippets are not layout changes, they are reported as diagnostics by the parser.
+pub fn check_layout(fingerprints: &[PageFingerprint], parsed_snippets: usize) -> Result<(), LayoutChangedError> {
+    if fingerprints.is_empty() {
+        return Err(LayoutChangedError{
+            message: "No pages were fetched".to_owned()
+        });
+    }
+    for (page_index, fingerprint) in fingerprints.iter().enumerate() {
+        let missing: Vec<&str> = fingerprint.class_counts.iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(class, _)| class.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(LayoutChangedError{
+                message: format!("Page {} has no elements with class {}", page_index, missing.join(", "))
+            });
+        }
+    }
+    if let Some(announced_total) = fingerprints[0].announced_total {
+        if announced_total as usize != parsed_snippets {
+            return Err(LayoutChangedError{
+                message: format!("The schedule says it has {} items, but {} were parsed", announced_total, parsed_snippets)
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(counts: &[(&str, usize)], announced_total: Option<u16>) -> PageFingerprint {
+        PageFingerprint{
+            class_counts: counts.iter()
+                .map(|(class, count)| (class.to_string(), *count))
+                .collect(),
+            announced_total,
+        }
+    }
+
+    #[test]
+    fn should_count_classes() {
+        let doc = Document::from("<div class=\"a\"><p class=\"b\"></p><p class=\"b\"></p></div>");
+        let fingerprint = PageFingerprint::of(&doc, &["a", "b", "c"], None);
+        assert_eq!(Some(&1), fingerprint.class_counts.get("a"));
+        assert_eq!(Some(&2), fingerprint.class_counts.get("b"));
+        assert_eq!(Some(&0), fingerprint.class_counts.get("c"));
+    }
+
+    #[test]
+    fn should_accept_expected_layout() {
+        let pages = vec![
+            fingerprint(&[("c-snippet", 30), ("c-snippet__title", 30)], Some(40)),
+            fingerprint(&[("c-snippet", 10), ("c-snippet__title", 10)], Some(40)),
+        ];
+        assert!(check_layout(&pages, 40).is_ok());
+    }
+
+    #[test]
+    fn should_reject_page_without_snippets() {
+        let pages = vec![fingerprint(&[("c-snippet", 0), ("c-snippet__title", 0)], None)];
+        assert!(check_layout(&pages, 0).unwrap_err().message.contains("c-snippet"));
+    }
+
+    #[test]
+    fn should_reject_missing_class() {
+        let pages = vec![fingerprint(&[("c-snippet", 30), ("c-snippet__meta", 0)], None)];
+        assert!(check_layout(&pages, 30).is_err());
+    }
+
+    #[test]
+    fn should_reject_total_mismatch() {
+        let pages = vec![fingerprint(&[("c-snippet", 30)], Some(177))];
+        assert!(check_layout(&pages, 30).is_err());
+    }
+
+    #[test]
+    fn should_reject_no_pages() {
+        assert!(check_layout(&[], 0).is_err());
+    }
+}
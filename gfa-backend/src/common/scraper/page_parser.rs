@@ -0,0 +1,436 @@
+use std::fmt;
+use std::error::Error;
+use std::result::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Datelike, Weekday, Utc};
+use chrono_tz::Tz;
+use select::{document, predicate};
+use serde::Serialize;
+use crate::pickup_event::PickUpEvent;
+use crate::scraper::calendar_words::Recognized;
+use crate::scraper::page_fetcher;
+use crate::scraper::page_fingerprint::PageFingerprint;
+use crate::scraper::schedule_grammar::{parse_schedule, Schedule, ScheduleErrorKind};
+
+#[derive(fmt::Debug)]
+pub struct PageParserError {
+    pub message: String,
+    pub causes: Vec<Box<dyn Error>>
+}
+impl fmt::Display for PageParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        if !self.causes.is_empty() {
+            writeln!(f, "Causes:")?;
+            for cause in &self.causes {
+                writeln!(f, "{}", cause)?;
+            }
+        } 
+        write!(f, "Total causes: {}", self.causes.len())
+    }
+}
+impl Error for PageParserError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+impl PageParserError {
+    fn new(message: String) -> PageParserError {
+        PageParserError{
+            message,
+            causes: Vec::new(),
+        }
+    }
+}
+
+/// Classes of the elements each snippet is parsed from.
+const SNIPPET_CLASSES: [&str; 4] = ["c-snippet", "c-snippet__title", "c-snippet__meta", "c-snippet__section"];
+
+type StartAndEndTime = (DateTime::<chrono_tz::Tz>, DateTime<chrono_tz::Tz>);
+
+// The page lists roughly half a year of visits, so the intended date is never further away from the scrape date than this.
+const MAX_DAYS_FROM_SCRAPE_DATE: i64 = 270;
+
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseStage {
+    Street,
+    District,
+    Split,
+    TimeParse,
+}
+
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The snippet was parsed, but something looked off.
+    Warning,
+    /// The snippet was skipped.
+    Error,
+}
+
+/// Describes a problem with a single c-snippet on a page.
+#[derive(fmt::Debug, Serialize)]
+pub struct SnippetDiagnostic {
+    pub page_index: usize,
+    pub snippet: String,
+    pub stage: ParseStage,
+    pub severity: Severity,
+    pub error: String,
+}
+impl fmt::Display for SnippetDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page {}, {:?} {:?}: {} (snippet: '{}')", self.page_index, self.stage, self.severity, self.error.trim(), self.snippet)
+    }
+}
+
+pub struct ParsedPage {
+    pub events: Vec<PickUpEvent>,
+    pub diagnostics: Vec<SnippetDiagnostic>,
+    pub total_snippets: usize,
+    pub fingerprint: PageFingerprint,
+}
+
+/// Parses a page in the Farligt Avfall-bilen layout into events for `service_id`, with times local to `time_zone`.
+pub fn parse_page(service_id: &str, time_zone: Tz, page: Vec<u8>, page_index: usize, scrape_date: NaiveDate) -> Result<ParsedPage, PageParserError> {
+    let doc = match document::Document::from_read(page.as_slice()) {
+        Ok(doc) => doc,
+        Err(_e) => return Err(PageParserError::new("Could not format HTML document".to_owned()))
+    };
+    let fingerprint = PageFingerprint::of(&doc, &SNIPPET_CLASSES, page_fetcher::announced_total(&doc).ok());
+    let mut events: Vec::<PickUpEvent> = Vec::new();
+    let mut diagnostics: Vec::<SnippetDiagnostic> = Vec::new();
+    let mut total_snippets = 0;
+    for node in doc.find(predicate::Class("c-snippet")) {
+        total_snippets += 1;
+        let diagnostic_with_severity = |stage: ParseStage, severity: Severity, error: String| SnippetDiagnostic{
+            page_index,
+            snippet: node.text().split_whitespace().collect::<Vec<&str>>().join(" "),
+            stage,
+            severity,
+            error,
+        };
+        let diagnostic = |stage: ParseStage, error: String| diagnostic_with_severity(stage, Severity::Error, error);
+        let street = match node.find(predicate::Class("c-snippet__title"))
+            .into_selection().children().first() {
+                Some(element) => format_street(element.text()), 
+                None => {
+                    diagnostics.push(diagnostic(ParseStage::Street, "No element with class c-snippet__title found".to_owned()));
+                    continue;
+                }
+            };
+        let meta = match node.find(predicate::Class("c-snippet__meta"))
+            .into_selection().first() {
+                Some(element) => match parse_meta(&element.text()) {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        diagnostics.push(diagnostic(ParseStage::District, e.to_string()));
+                        continue;
+                    }
+                },
+                None => {
+                    diagnostics.push(diagnostic(ParseStage::District, "No element with class c-snippet__meta found".to_owned()));
+                    continue;
+                }
+            };
+        let other_stuff = match node.find(predicate::Class("c-snippet__section"))
+            .into_selection().first() {
+                Some(element) => element.text(),
+                None => {
+                    diagnostics.push(diagnostic(ParseStage::Split, "No element with class c-snippet__section found".to_owned()));
+                    continue;
+                }
+            };
+        let schedule = match parse_schedule(&other_stuff) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                let stage = match e.kind {
+                    ScheduleErrorKind::MissingDates => ParseStage::Split,
+                    ScheduleErrorKind::Syntax => ParseStage::TimeParse,
+                };
+                diagnostics.push(diagnostic(stage, e.to_string()));
+                continue;
+            }
+        };
+        let times: Vec<StartAndEndTime> = match resolve_times(&schedule, scrape_date, time_zone) {
+            Ok((times, warnings)) => {
+                for warning in warnings {
+                    diagnostics.push(diagnostic_with_severity(ParseStage::TimeParse, Severity::Warning, warning));
+                }
+                times
+            },
+            Err(e) => {
+                diagnostics.push(diagnostic(ParseStage::TimeParse, e.to_string()));
+                continue;
+            }
+        };
+        let mut snippet_events: Vec::<PickUpEvent> = Vec::new();
+        // resolve_times gives one start and end time for each entry, in the same order
+        for (t, entry) in times.into_iter().zip(&schedule.entries) {
+            match PickUpEvent::new(service_id, String::from(&street), meta.area.clone(), meta.organisation_type.clone(), schedule.description.clone(), t.0.with_timezone(&Utc), t.1.with_timezone(&Utc)) {
+                Ok(event) => {
+                    snippet_events.push(event.with_status(entry.status));
+                },
+                Err(e) => {
+                    diagnostics.push(diagnostic(ParseStage::TimeParse, e.to_string()));
+                    snippet_events.clear();
+                    break;
+                }
+            };
+        }
+        events.append(&mut snippet_events);
+    }
+    Ok(ParsedPage{
+        events,
+        diagnostics,
+        total_snippets,
+        fingerprint,
+    })
+} 
+
+fn format_street(raw: String) -> String {
+    String::from(raw.trim())
+}
+
+/// The c-snippet__meta line, such as "Kommunal, Västra Göteborg". The same values are used by the
+/// filterOrganisationType and filterArea parameters on the page.
+#[derive(fmt::Debug, PartialEq)]
+struct SnippetMeta {
+    organisation_type: Option<String>,
+    area: String,
+}
+
+fn parse_meta(raw: &str) -> Result<SnippetMeta, PageParserError> {
+    let (organisation_type, area) = match raw.split_once(',') {
+        Some((organisation_type, area)) => (Some(organisation_type.trim()).filter(|organisation_type| !organisation_type.is_empty()), area.trim()),
+        None => (None, raw.trim()),
+    };
+    if area.is_empty() {
+        return Err(PageParserError::new(format!("No area found in c-snippet__meta: '{}'", raw.trim())));
+    }
+    Ok(SnippetMeta{
+        organisation_type: organisation_type.map(|organisation_type| organisation_type.to_owned()),
+        area: area.to_owned(),
+    })
+}
+
+/// Places the dates of a parsed schedule in time, and returns them together with warnings about misspelled names and weekdays which did not match the inferred date.
+fn resolve_times(schedule: &Schedule, scrape_date: NaiveDate, time_zone: Tz) -> Result<(Vec<StartAndEndTime>, Vec<String>), PageParserError> {
+    let mut datetimes: Vec::<StartAndEndTime> = Vec::new();
+    let mut warnings: Vec::<String> = Vec::new();
+    for entry in &schedule.entries {
+        if !entry.weekday.recognized.is_exact() {
+            warnings.push(fuzzy_match_warning(&entry.weekday.raw, &entry.weekday.recognized));
+        }
+        if !entry.month.recognized.is_exact() {
+            warnings.push(fuzzy_match_warning(&entry.month.raw, &entry.month.recognized));
+        }
+        let (date, warning) = infer_date(entry.day, entry.month.recognized.value, &entry.weekday.raw, entry.weekday.recognized.value, scrape_date)?;
+        if let Some(warning) = warning {
+            warnings.push(warning);
+        }
+        let start = local_datetime(date.and_time(entry.start), time_zone)?;
+        let end = local_datetime(date.and_time(entry.end), time_zone)?;
+        datetimes.push((start, end));
+    }
+    Ok((datetimes, warnings))
+}
+
+fn local_datetime(datetime: NaiveDateTime, time_zone: Tz) -> Result<DateTime<Tz>, PageParserError> {
+    match time_zone.from_local_datetime(&datetime).single() {
+        Some(datetime) => Ok(datetime),
+        None => Err(PageParserError::new(format!("Ambiguous or non-existent local time: {}", datetime)))
+    }
+}
+
+fn fuzzy_match_warning<T>(word: &str, recognized: &Recognized<T>) -> String {
+    format!("Interpreted '{}' as '{}' (confidence {:.2})", word, recognized.canonical, recognized.confidence)
+}
+
+/// The page never mentions a year, so it is inferred from the scrape date: among the years around the scrape date,
+/// the one where the Swedish weekday matches the date is picked. If no year matches, the date closest to the scrape date
+/// is used, and a warning describing the mismatch is returned with it.
+fn infer_date(day: u32, month: u32, swe_weekday: &str, weekday: Weekday, scrape_date: NaiveDate) -> Result<(NaiveDate, Option<String>), PageParserError> {
+    let mut candidates: Vec<NaiveDate> = (scrape_date.year() - 1..=scrape_date.year() + 1)
+        .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+        .filter(|date| (*date - scrape_date).num_days().abs() <= MAX_DAYS_FROM_SCRAPE_DATE)
+        .collect();
+    candidates.sort_by_key(|date| (*date - scrape_date).num_days().abs());
+    let closest = match candidates.first() {
+        Some(date) => *date,
+        None => return Err(PageParserError::new(format!("Invalid date: {}/{}", day, month)))
+    };
+    match candidates.iter().find(|date| date.weekday() == weekday) {
+        Some(date) => Ok((*date, None)),
+        None => Ok((closest, Some(format!("Weekday '{}' does not match {}, which is a {}", swe_weekday, closest, closest.weekday()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pickup_event::FARLIGT_AVFALL_SERVICE_ID;
+    use crate::scraper::read_test_resource;
+
+    fn scrape_date(year: i32) -> NaiveDate {
+        NaiveDate::from_ymd(year, 9, 1)
+    }
+
+    fn parse_times(raw: &str, scrape_date: NaiveDate) -> Result<(Vec<StartAndEndTime>, Vec<String>), PageParserError> {
+        resolve_times(&parse_schedule(raw).unwrap(), scrape_date, Tz::Europe__Stockholm)
+    }
+
+    #[test]
+    fn should_format_street() {
+        let raw_street = "
+                                    Bankebergsgatan/Kennedygatan";
+        let formatted_street = format_street(String::from(raw_street));
+        assert_eq!("Bankebergsgatan/Kennedygatan", formatted_street);
+    }
+
+    #[test]
+    fn should_parse_meta() {
+        let meta = parse_meta("Kommunal, Västra Göteborg").unwrap();
+        assert_eq!(Some("Kommunal".to_owned()), meta.organisation_type);
+        assert_eq!("Västra Göteborg", meta.area);
+    }
+
+    #[test]
+    fn should_parse_meta_with_other_organisation_type() {
+        let meta = parse_meta(" Privat,Norra Hisingen ").unwrap();
+        assert_eq!(Some("Privat".to_owned()), meta.organisation_type);
+        assert_eq!("Norra Hisingen", meta.area);
+    }
+
+    #[test]
+    fn should_parse_meta_without_organisation_type() {
+        let meta = parse_meta("Centrum").unwrap();
+        assert_eq!(None, meta.organisation_type);
+        assert_eq!("Centrum", meta.area);
+        assert!(parse_meta("Kommunal, ").is_err());
+    }
+
+    #[test]
+    fn should_warn_on_misspelled_names() {
+        let raw = "tossdag 1 okt 18-18.45 och tisdag 29 sepetmber 19-19.30";
+        let (times, warnings) = parse_times(raw, scrape_date(2020)).unwrap();
+        assert_eq!("2020-10-01T18:00:00+02:00", times[0].0.to_rfc3339());
+        assert_eq!("2020-09-29T19:00:00+02:00", times[1].0.to_rfc3339());
+        assert_eq!(2, warnings.len());
+    }
+
+    #[test]
+    fn should_parse_single_timestamp_without_minutes_in_start_time() {
+        let raw = "Måndag 28 september 17-17.45";
+        let time = parse_times(&raw.to_owned(), scrape_date(2020)).unwrap().0;
+        assert_eq!(1, time.len());
+        assert_eq!("2020-09-28T17:00:00+02:00".to_owned(), time.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2020-09-28T17:45:00+02:00", time.get(0).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_handle_daytime_comma_separator() {
+        let raw = "torsdag 23 september, 17.00-17.20";
+        let time = parse_times(&raw.to_owned(), scrape_date(2021)).unwrap().0;
+        assert_eq!(1, time.len());
+        assert_eq!("2021-09-23T17:00:00+02:00".to_owned(), time.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2021-09-23T17:20:00+02:00".to_owned(), time.get(0).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_handle_daytime_dot_separator() {
+        let raw = "tisdag 19 oktober. 18.00-18.45";
+        let time = parse_times(&raw.to_owned(), scrape_date(2021)).unwrap().0;
+        assert_eq!(1, time.len());
+        assert_eq!("2021-10-19T18:00:00+02:00".to_owned(), time.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2021-10-19T18:45:00+02:00".to_owned(), time.get(0).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_parse_single_timestamp_without_minutes_in_end_time() {
+        let raw = "måndag 29 mars 19.15-20";
+        let time = parse_times(&raw.to_owned(), scrape_date(2021)).unwrap().0;
+        assert_eq!(1, time.len());
+        assert_eq!("2021-03-29T19:15:00+02:00".to_owned(), time.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2021-03-29T20:00:00+02:00".to_owned(), time.get(0).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_parse_multiple_timestamps() {
+        let raw = "Torsdag 17 september 17-17.20 och torsdag 20 oktober 17-17.20";
+        let times = parse_times(&raw.to_owned(), scrape_date(2020)).unwrap().0;
+        assert_eq!(2, times.len());
+        assert_eq!("2020-10-20T17:00:00+02:00".to_owned(), times.get(1).unwrap().0.to_rfc3339());
+        assert_eq!("2020-10-20T17:20:00+02:00".to_owned(), times.get(1).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_handle_timestamp_with_bad_spacing() {
+        let raw = "tisdag 27 oktober 18.10- 18.30";
+        let times = parse_times(&raw.to_owned(), scrape_date(2020)).unwrap().0;
+        assert_eq!("2020-10-27T18:10:00+01:00".to_owned(), times.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2020-10-27T18:30:00+01:00".to_owned(), times.get(0).unwrap().1.to_rfc3339());
+    }
+
+    #[test]
+    fn should_handle_daylight_saving() {
+        let with_dst = "Tisdag 24 oktober 16-16.20";
+        let without_dst = "Måndag 25 oktober 16-16.20";
+        assert_eq!("2020-10-24T16:00:00+02:00", parse_times(&with_dst.to_string(), scrape_date(2020)).unwrap().0.get(0).unwrap().0.to_rfc3339());
+        assert_eq!("2020-10-25T16:00:00+01:00", parse_times(&without_dst.to_string(), scrape_date(2020)).unwrap().0.get(0).unwrap().0.to_rfc3339());
+    }
+
+    #[test]
+    fn should_infer_next_year_for_schedule_spanning_new_year() {
+        let raw = "tisdag 12 januari 18-18.45";
+        let (times, warnings) = parse_times(raw, NaiveDate::from_ymd(2020, 12, 15)).unwrap();
+        assert_eq!("2021-01-12T18:00:00+01:00", times[0].0.to_rfc3339());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn should_infer_previous_year_for_passed_visits() {
+        let raw = "tisdag 15 december 18-18.45";
+        let (times, warnings) = parse_times(raw, NaiveDate::from_ymd(2021, 1, 1)).unwrap();
+        assert_eq!("2020-12-15T18:00:00+01:00", times[0].0.to_rfc3339());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn should_pick_year_where_weekday_matches() {
+        // 30 june is a tuesday in 2020, but a wednesday in 2021, which is closer to the scrape date
+        let raw = "tisdag 30 juni 18-18.45";
+        let (times, warnings) = parse_times(raw, NaiveDate::from_ymd(2021, 3, 15)).unwrap();
+        assert_eq!("2020-06-30T18:00:00+02:00", times[0].0.to_rfc3339());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn should_warn_on_weekday_mismatch() {
+        let raw = "onsdag 12 januari 18-18.45";
+        let (times, warnings) = parse_times(raw, NaiveDate::from_ymd(2020, 12, 15)).unwrap();
+        assert_eq!("2021-01-12T18:00:00+01:00", times[0].0.to_rfc3339());
+        assert_eq!(1, warnings.len());
+    }
+
+    #[test]
+    fn should_parse_full_page() {
+        let file = read_test_resource("body_with_items.html");
+        let parsed = parse_page(FARLIGT_AVFALL_SERVICE_ID, Tz::Europe__Stockholm, file, 0, scrape_date(2020)).unwrap();
+        assert_eq!(39, parsed.events.len());
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!("Angered", parsed.events[0].district);
+        assert!(parsed.events[0].location_id.starts_with("farligt-avfall:angered_"));
+        assert_eq!(Some("Kommunal".to_owned()), parsed.events[0].organisation_type);
+    }
+
+    #[test]
+    fn should_return_good_events_and_diagnostics_for_bad_snippets() {
+        let file = read_test_resource("body_with_very_bad_content.html");
+        let parsed = parse_page(FARLIGT_AVFALL_SERVICE_ID, Tz::Europe__Stockholm, file, 3, scrape_date(2020)).unwrap();
+        assert_eq!(2, parsed.diagnostics.len());
+        assert_eq!(37, parsed.events.len());
+        assert_eq!(3, parsed.diagnostics[0].page_index);
+        assert_eq!(ParseStage::TimeParse, parsed.diagnostics[0].stage);
+        assert_eq!(ParseStage::Split, parsed.diagnostics[1].stage);
+    }
+}
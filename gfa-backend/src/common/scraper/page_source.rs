@@ -0,0 +1,286 @@
+use std::{collections::BTreeMap, fmt, fs, path::{Path, PathBuf}, sync::Mutex, time::Duration};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode, header::{self, HeaderMap}};
+use serde::{Serialize, Deserialize};
+use crate::scraper::page_fetcher::PageFetcherError;
+
+const INDEX_FILE: &str = "index.json";
+const USER_AGENT: &str = concat!("gbg-farligt-avfall-scraper/", env!("CARGO_PKG_VERSION"), " (+https://github.com/jorand-hogia/gbg-farligt-avfall)");
+
+/// `ETag` and `Last-Modified` from a response, to be sent back on the next request for the same page.
+#[derive(fmt::Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+#[derive(fmt::Debug, PartialEq)]
+pub enum ConditionalPage {
+    Modified(Vec<u8>, Validators),
+    NotModified,
+}
+
+#[async_trait]
+pub trait PageSource {
+    async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError>;
+
+    /// Sources which cannot make conditional requests always return the full page, without validators.
+    async fn fetch_if_modified(&self, path: &str, _validators: &Validators) -> Result<ConditionalPage, PageFetcherError> {
+        Ok(ConditionalPage::Modified(self.fetch(path).await?, Validators::default()))
+    }
+}
+
+pub struct HttpPageSource {
+    client: Client,
+    base_url: String,
+}
+
+impl HttpPageSource {
+    pub fn new(base_url: &str, timeout: Duration) -> Result<Self, PageFetcherError> {
+        let client = match Client::builder()
+            .use_rustls_tls()
+            .user_agent(USER_AGENT)
+            .timeout(timeout)
+            .build() {
+            Ok(client) => client,
+            Err(e) => return Err(PageFetcherError{
+                message: format!("Could not create HTTP client: {}", e),
+                retryable: false,
+            })
+        };
+        Ok(HttpPageSource{
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl PageSource for HttpPageSource {
+    async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError> {
+        match self.fetch_if_modified(path, &Validators::default()).await? {
+            ConditionalPage::Modified(page, _) => Ok(page),
+            ConditionalPage::NotModified => Err(PageFetcherError{
+                message: format!("Unexpected 304 Not Modified for unconditional request to {}{}", self.base_url, path),
+                retryable: false,
+            })
+        }
+    }
+
+    async fn fetch_if_modified(&self, path: &str, validators: &Validators) -> Result<ConditionalPage, PageFetcherError> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self.client.get(&url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = match request.send().await {
+            Ok(res) => res,
+            Err(e) => return Err(PageFetcherError{
+                message: format!("Request to {} failed: {}", url, e),
+                retryable: e.is_connect() || e.is_timeout(),
+            })
+        };
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(ConditionalPage::NotModified);
+        }
+        if !response.status().is_success() {
+            return Err(PageFetcherError{
+                message: format!("Non-OK status code from {}: {}", url, response.status()),
+                // Other client errors, e.g. 404 Not Found, will not go away by asking again
+                retryable: response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS,
+            });
+        }
+        let validators = validators_from_headers(response.headers());
+        match response.bytes().await {
+            Ok(bytes) => Ok(ConditionalPage::Modified(bytes.to_vec(), validators)),
+            Err(e) => Err(PageFetcherError{
+                message: format!("Failed to read response body as bytes from: {}", url),
+                retryable: e.is_timeout(),
+            })
+        }
+    }
+}
+
+fn validators_from_headers(headers: &HeaderMap) -> Validators {
+    let header_value = |name: header::HeaderName| headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned());
+    Validators{
+        etag: header_value(header::ETAG),
+        last_modified: header_value(header::LAST_MODIFIED),
+    }
+}
+
+/// Serves pages from a directory previously written by `RecordingPageSource`.
+/// The directory holds an `index.json` which maps each requested path to a file name in the same directory.
+pub struct ReplayPageSource {
+    directory: PathBuf,
+    index: BTreeMap<String, String>,
+}
+
+impl ReplayPageSource {
+    pub fn new(directory: PathBuf) -> Result<Self, PageFetcherError> {
+        let index = read_index(&directory)?;
+        Ok(ReplayPageSource{
+            directory,
+            index,
+        })
+    }
+}
+
+#[async_trait]
+impl PageSource for ReplayPageSource {
+    async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError> {
+        let file_name = match self.index.get(path) {
+            Some(file_name) => file_name,
+            None => return Err(PageFetcherError{
+                message: format!("No recorded page for: {}", path),
+                retryable: false,
+            })
+        };
+        let file_path = self.directory.join(file_name);
+        match fs::read(&file_path) {
+            Ok(page) => Ok(page),
+            Err(e) => Err(PageFetcherError{
+                message: format!("Could not read recorded page {}: {}", file_path.display(), e),
+                retryable: false,
+            })
+        }
+    }
+}
+
+/// Passes every request on to another source, and writes each fetched page to a directory which `ReplayPageSource` can read.
+pub struct RecordingPageSource<S: PageSource> {
+    inner: S,
+    directory: PathBuf,
+    index: Mutex<BTreeMap<String, String>>,
+}
+
+impl<S: PageSource> RecordingPageSource<S> {
+    pub fn new(inner: S, directory: PathBuf) -> Result<Self, PageFetcherError> {
+        if let Err(e) = fs::create_dir_all(&directory) {
+            return Err(PageFetcherError{
+                message: format!("Could not create recording directory {}: {}", directory.display(), e),
+                retryable: false,
+            });
+        }
+        Ok(RecordingPageSource{
+            inner,
+            directory,
+            index: Mutex::new(BTreeMap::new()),
+        })
+    }
+}
+
+#[async_trait]
+impl<S: PageSource + Send + Sync> PageSource for RecordingPageSource<S> {
+    async fn fetch(&self, path: &str) -> Result<Vec<u8>, PageFetcherError> {
+        let page = self.inner.fetch(path).await?;
+        self.record(path, &page)?;
+        Ok(page)
+    }
+
+    async fn fetch_if_modified(&self, path: &str, validators: &Validators) -> Result<ConditionalPage, PageFetcherError> {
+        let result = self.inner.fetch_if_modified(path, validators).await?;
+        if let ConditionalPage::Modified(page, _) = &result {
+            self.record(path, page)?;
+        }
+        Ok(result)
+    }
+}
+
+impl<S: PageSource> RecordingPageSource<S> {
+    fn record(&self, path: &str, page: &[u8]) -> Result<(), PageFetcherError> {
+        let mut index = self.index.lock().unwrap();
+        let file_name = match index.get(path) {
+            Some(file_name) => file_name.clone(),
+            None => format!("page-{}.html", index.len()),
+        };
+        if let Err(e) = fs::write(self.directory.join(&file_name), page) {
+            return Err(PageFetcherError{
+                message: format!("Could not record page {}: {}", path, e),
+                retryable: false,
+            });
+        }
+        index.insert(path.to_owned(), file_name);
+        write_index(&self.directory, &index)
+    }
+}
+
+fn read_index(directory: &Path) -> Result<BTreeMap<String, String>, PageFetcherError> {
+    let index_path = directory.join(INDEX_FILE);
+    let content = match fs::read(&index_path) {
+        Ok(content) => content,
+        Err(e) => return Err(PageFetcherError{
+            message: format!("Could not read {}: {}", index_path.display(), e),
+            retryable: false,
+        })
+    };
+    match serde_json::from_slice(&content) {
+        Ok(index) => Ok(index),
+        Err(e) => Err(PageFetcherError{
+            message: format!("Malformed {}: {}", index_path.display(), e),
+            retryable: false,
+        })
+    }
+}
+
+fn write_index(directory: &Path, index: &BTreeMap<String, String>) -> Result<(), PageFetcherError> {
+    let index_path = directory.join(INDEX_FILE);
+    let content = match serde_json::to_vec_pretty(index) {
+        Ok(content) => content,
+        Err(e) => return Err(PageFetcherError{
+            message: format!("Could not serialize page index: {}", e),
+            retryable: false,
+        })
+    };
+    match fs::write(&index_path, content) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(PageFetcherError{
+            message: format!("Could not write {}: {}", index_path.display(), e),
+            retryable: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::city_profile::CityProfile;
+    use crate::scraper::test_resources;
+    use std::env;
+
+    #[tokio::test]
+    async fn should_replay_recorded_page() {
+        let source = ReplayPageSource::new(test_resources()).unwrap();
+        let page = source.fetch(&CityProfile::goteborg().sources[0].path).await.unwrap();
+        assert_eq!(fs::read(test_resources().join("body_with_items.html")).unwrap(), page);
+    }
+
+    #[tokio::test]
+    async fn should_fail_on_unknown_path() {
+        let source = ReplayPageSource::new(test_resources()).unwrap();
+        assert!(source.fetch("/not/recorded").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_record_pages_for_replay() {
+        let directory = env::temp_dir().join(format!("gfa-recording-{}", std::process::id()));
+        let recorder = RecordingPageSource::new(ReplayPageSource::new(test_resources()).unwrap(), directory.clone()).unwrap();
+        let recorded = recorder.fetch(&CityProfile::goteborg().sources[0].path).await.unwrap();
+
+        let replayed = ReplayPageSource::new(directory.clone()).unwrap()
+            .fetch(&CityProfile::goteborg().sources[0].path).await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(recorded, replayed);
+    }
+}
@@ -0,0 +1,605 @@
+use std::{fmt, error};
+use chrono::{NaiveTime, Weekday};
+use crate::pickup_event::EventStatus;
+use crate::scraper::calendar_words::{recognize_month, recognize_weekday, Recognized};
+
+// Grammar for the text in a c-snippet__section, after lowercasing:
+//
+//   schedule    = [ description "." ] date-list [ "." ]
+//   date-list   = item { separator item }
+//   item        = [ prefix [ ":" ] ] date [ "," | "." ] [ new-time ] [ time-range ] [ suffix ]
+//   date        = weekday [ "," ] day month
+//   time-range  = [ "kl" [ "." ] ] time dash time
+//   time        = hour [ ( "." | ":" ) minute ]
+//   dash        = "-" | "–" | "—"
+//   separator   = { "," | "." | "och" | "samt" }
+//   prefix      = cancelled | new-time | "nytt" "datum"
+//   new-time    = "ny" "tid" [ ":" ]
+//   suffix      = [ "(" ] ( cancelled | moved "till" [ time-range ] ) [ ")" ]
+//   cancelled   = "inställt" | "inställd" | "inställs"
+//   moved       = "flyttad" | "flyttat" | "flyttas"
+//
+// A time range applies to every date since the previous time range, so "måndag 5 oktober och tisdag 6 oktober 18-18.45"
+// gives two entries with the same times.
+//
+// A cancelled suffix applies to the whole item. "Flyttad till" cancels the item and marks the item after it as moved,
+// so "tisdag 6 oktober 18-18.45 flyttad till onsdag 7 oktober 18-18.45" gives one cancelled and one moved entry.
+// When only a time range follows "flyttad till", the item itself is moved to that time.
+
+#[derive(fmt::Debug, PartialEq)]
+pub struct Schedule {
+    pub description: Option<String>,
+    pub entries: Vec<ScheduleEntry>,
+}
+
+#[derive(fmt::Debug, Clone, PartialEq)]
+pub struct ScheduleEntry {
+    pub weekday: CalendarWord<Weekday>,
+    pub day: u32,
+    pub month: CalendarWord<u32>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub status: EventStatus,
+}
+
+/// A weekday or month as written on the page, together with what it was recognized as.
+#[derive(fmt::Debug, Clone, PartialEq)]
+pub struct CalendarWord<T> {
+    pub raw: String,
+    pub recognized: Recognized<T>,
+}
+
+#[derive(fmt::Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleErrorKind {
+    /// No weekday followed by a date was found, so the text could not be split into description and dates.
+    MissingDates,
+    /// Dates were found, but they did not follow the grammar.
+    Syntax,
+}
+
+#[derive(fmt::Debug)]
+pub struct ScheduleError {
+    pub kind: ScheduleErrorKind,
+    pub message: String,
+}
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for ScheduleError {}
+
+#[derive(fmt::Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Number(u32),
+    Dot,
+    Colon,
+    Comma,
+    Dash,
+    Other(char),
+}
+
+#[derive(fmt::Debug)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+pub fn parse_schedule(raw: &str) -> Result<Schedule, ScheduleError> {
+    let text = raw.trim().to_lowercase();
+    let tokens = tokenize(&text);
+    let mut first_error: Option<ScheduleError> = None;
+    for start in (0..tokens.len()).filter(|index| is_date_list_start(&tokens, *index)) {
+        let mut parser = Parser{
+            tokens: &tokens,
+            position: start,
+        };
+        match parser.date_list() {
+            Ok(entries) => {
+                let description = text[0..tokens[start].offset].replace(".", "").trim().to_owned();
+                return Ok(Schedule{
+                    description: if description.is_empty() { None } else { Some(description) },
+                    entries,
+                });
+            },
+            Err(e) => if first_error.is_none() {
+                first_error = Some(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or(ScheduleError{
+        kind: ScheduleErrorKind::MissingDates,
+        message: format!("No swedish day name found while splitting description and times: {}", text),
+    }))
+}
+
+/// Dates start either first in the text or directly after a sentence, with a weekday followed by a day number.
+/// The weekday may be preceded by a status, as in "inställt: tisdag 6 oktober".
+fn is_date_list_start(tokens: &[Token], index: usize) -> bool {
+    if index > 0 && tokens[index - 1].kind != TokenKind::Dot {
+        return false;
+    }
+    let mut parser = Parser{
+        tokens,
+        position: index,
+    };
+    parser.status_prefix();
+    let is_weekday = match parser.next() {
+        Some(TokenKind::Word(word)) => recognize_weekday(&word).is_some(),
+        _ => false,
+    };
+    let day = tokens[parser.position..].iter()
+        .find(|token| token.kind != TokenKind::Comma);
+    is_weekday && matches!(day, Some(Token{ kind: TokenKind::Number(_), .. }))
+}
+
+fn is_cancelled_word(word: &str) -> bool {
+    matches!(word, "inställt" | "inställd" | "inställs")
+}
+
+fn is_moved_word(word: &str) -> bool {
+    matches!(word, "flyttad" | "flyttat" | "flyttas")
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let kind = if c.is_whitespace() {
+            continue;
+        } else if c.is_alphabetic() {
+            let mut word = c.to_string();
+            while let Some((_, next)) = chars.peek().filter(|(_, next)| next.is_alphabetic()) {
+                word.push(*next);
+                chars.next();
+            }
+            TokenKind::Word(word)
+        } else if c.is_ascii_digit() {
+            let mut number = c.to_string();
+            while let Some((_, next)) = chars.peek().filter(|(_, next)| next.is_ascii_digit()) {
+                number.push(*next);
+                chars.next();
+            }
+            TokenKind::Number(number.parse().unwrap_or(u32::MAX))
+        } else {
+            match c {
+                '.' => TokenKind::Dot,
+                ':' => TokenKind::Colon,
+                ',' => TokenKind::Comma,
+                '-' | '–' | '—' => TokenKind::Dash,
+                other => TokenKind::Other(other),
+            }
+        };
+        tokens.push(Token{ kind, offset });
+    }
+    tokens
+}
+
+struct PendingDate {
+    weekday: CalendarWord<Weekday>,
+    day: u32,
+    month: CalendarWord<u32>,
+    status: EventStatus,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn date_list(&mut self) -> Result<Vec<ScheduleEntry>, ScheduleError> {
+        let mut entries: Vec<ScheduleEntry> = Vec::new();
+        let mut pending_dates: Vec<PendingDate> = Vec::new();
+        let mut next_status = EventStatus::Scheduled;
+        loop {
+            if let Some(status) = self.status_prefix() {
+                next_status = status;
+            }
+            let (weekday, day, month) = self.date()?;
+            pending_dates.push(PendingDate{ weekday, day, month, status: next_status });
+            next_status = EventStatus::Scheduled;
+            let item_start = entries.len();
+            while self.eat(&TokenKind::Comma) || self.eat(&TokenKind::Dot) {}
+            if self.eat_new_time() {
+                if let Some(pending) = pending_dates.last_mut() {
+                    pending.status = EventStatus::Moved;
+                }
+            }
+            if self.at_time_range() {
+                let (start, end) = self.time_range()?;
+                for pending in pending_dates.drain(..) {
+                    entries.push(ScheduleEntry{
+                        weekday: pending.weekday,
+                        day: pending.day,
+                        month: pending.month,
+                        start,
+                        end,
+                        status: pending.status,
+                    });
+                }
+            }
+            match self.status_suffix()? {
+                // Moved to another time on the same day
+                Some(true) if self.at_time_range() && pending_dates.is_empty() => {
+                    let (start, end) = self.time_range()?;
+                    for entry in &mut entries[item_start..] {
+                        entry.start = start;
+                        entry.end = end;
+                        entry.status = EventStatus::Moved;
+                    }
+                },
+                Some(moved) => {
+                    for entry in &mut entries[item_start..] {
+                        entry.status = EventStatus::Cancelled;
+                    }
+                    if let Some(pending) = pending_dates.last_mut() {
+                        pending.status = EventStatus::Cancelled;
+                    }
+                    if moved {
+                        next_status = EventStatus::Moved;
+                        continue;
+                    }
+                },
+                None => (),
+            }
+            while self.eat(&TokenKind::Comma) || self.eat(&TokenKind::Dot) || self.eat(&TokenKind::Other(')')) || self.eat_word("och") || self.eat_word("samt") {}
+            if self.peek().is_none() {
+                break;
+            }
+        }
+        if let Some(pending) = pending_dates.first() {
+            return Err(self.error(format!("Missing time for {} {} {}", pending.weekday.raw, pending.day, pending.month.raw)));
+        }
+        Ok(entries)
+    }
+
+    /// A status written before a date, as in "inställt: tisdag 6 oktober" or "ny tid: tisdag 6 oktober".
+    fn status_prefix(&mut self) -> Option<EventStatus> {
+        if self.eat_new_time() {
+            return Some(EventStatus::Moved);
+        }
+        let status = match self.peek_word_at(0) {
+            Some(word) if is_cancelled_word(word) => {
+                self.position += 1;
+                EventStatus::Cancelled
+            },
+            Some("nytt") if self.peek_word_at(1) == Some("datum") => {
+                self.position += 2;
+                EventStatus::Moved
+            },
+            _ => return None,
+        };
+        self.eat(&TokenKind::Colon);
+        Some(status)
+    }
+
+    fn eat_new_time(&mut self) -> bool {
+        if self.peek_word_at(0) == Some("ny") && self.peek_word_at(1) == Some("tid") {
+            self.position += 2;
+            self.eat(&TokenKind::Colon);
+            return true;
+        }
+        false
+    }
+
+    /// Returns `Some(true)` for "flyttad till", which must be followed by the new date, and `Some(false)` if the item is cancelled.
+    fn status_suffix(&mut self) -> Result<Option<bool>, ScheduleError> {
+        let start = self.position;
+        let parenthesized = self.eat(&TokenKind::Other('('));
+        let moved = match self.next() {
+            Some(TokenKind::Word(word)) if is_cancelled_word(&word) => false,
+            Some(TokenKind::Word(word)) if is_moved_word(&word) => {
+                if !self.eat_word("till") {
+                    return Err(self.error(format!("Expected 'till' after '{}'", word)));
+                }
+                true
+            },
+            _ => {
+                self.position = start;
+                return Ok(None);
+            }
+        };
+        if parenthesized {
+            self.eat(&TokenKind::Other(')'));
+        }
+        Ok(Some(moved))
+    }
+
+    fn date(&mut self) -> Result<(CalendarWord<Weekday>, u32, CalendarWord<u32>), ScheduleError> {
+        let weekday = match self.next() {
+            Some(TokenKind::Word(word)) => match recognize_weekday(&word) {
+                Some(recognized) => CalendarWord{ raw: word, recognized },
+                None => return Err(self.error(format!("Expected a weekday, found '{}'", word)))
+            },
+            other => return Err(self.unexpected("a weekday", other))
+        };
+        self.eat(&TokenKind::Comma);
+        let day = match self.next() {
+            Some(TokenKind::Number(day)) if (1..=31).contains(&day) => day,
+            other => return Err(self.unexpected("a day number", other))
+        };
+        let month = match self.next() {
+            Some(TokenKind::Word(word)) => match recognize_month(&word) {
+                Some(recognized) => CalendarWord{ raw: word, recognized },
+                None => return Err(self.error(format!("Invalid month name: {}", word)))
+            },
+            other => return Err(self.unexpected("a month", other))
+        };
+        Ok((weekday, day, month))
+    }
+
+    fn at_time_range(&self) -> bool {
+        match self.peek() {
+            Some(TokenKind::Number(_)) => true,
+            Some(TokenKind::Word(word)) => word == "kl",
+            _ => false,
+        }
+    }
+
+    fn time_range(&mut self) -> Result<(NaiveTime, NaiveTime), ScheduleError> {
+        if self.eat_word("kl") {
+            self.eat(&TokenKind::Dot);
+        }
+        let start = self.time()?;
+        match self.next() {
+            Some(TokenKind::Dash) => (),
+            other => return Err(self.unexpected("'-' between start and end time", other))
+        };
+        let end = self.time()?;
+        if end <= start {
+            return Err(self.error(format!("End time {} is not after start time {}", end.format("%H:%M"), start.format("%H:%M"))));
+        }
+        Ok((start, end))
+    }
+
+    fn time(&mut self) -> Result<NaiveTime, ScheduleError> {
+        let hour = match self.next() {
+            Some(TokenKind::Number(hour)) => hour,
+            other => return Err(self.unexpected("an hour", other))
+        };
+        let has_minutes = matches!(self.peek(), Some(TokenKind::Dot) | Some(TokenKind::Colon))
+            && matches!(self.tokens.get(self.position + 1).map(|token| &token.kind), Some(TokenKind::Number(_)));
+        let minute = if has_minutes {
+            self.next();
+            match self.next() {
+                Some(TokenKind::Number(minute)) => minute,
+                other => return Err(self.unexpected("minutes", other))
+            }
+        } else {
+            0
+        };
+        match NaiveTime::from_hms_opt(hour, minute, 0) {
+            Some(time) => Ok(time),
+            None => Err(self.error(format!("Invalid time: {}.{:02}", hour, minute)))
+        }
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    fn peek_word_at(&self, offset: usize) -> Option<&str> {
+        match self.tokens.get(self.position + offset).map(|token| &token.kind) {
+            Some(TokenKind::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Option<TokenKind> {
+        let kind = self.peek().cloned();
+        if kind.is_some() {
+            self.position += 1;
+        }
+        kind
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == Some(kind) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_word(&mut self, expected: &str) -> bool {
+        match self.peek() {
+            Some(TokenKind::Word(word)) if word == expected => {
+                self.position += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn error(&self, message: String) -> ScheduleError {
+        ScheduleError{
+            kind: ScheduleErrorKind::Syntax,
+            message,
+        }
+    }
+
+    fn unexpected(&self, expected: &str, found: Option<TokenKind>) -> ScheduleError {
+        let found = match found {
+            Some(TokenKind::Word(word)) => format!("'{}'", word),
+            Some(TokenKind::Number(number)) => format!("'{}'", number),
+            Some(TokenKind::Dot) => "'.'".to_owned(),
+            Some(TokenKind::Colon) => "':'".to_owned(),
+            Some(TokenKind::Comma) => "','".to_owned(),
+            Some(TokenKind::Dash) => "'-'".to_owned(),
+            Some(TokenKind::Other(other)) => format!("'{}'", other),
+            None => "end of text".to_owned(),
+        };
+        self.error(format!("Expected {}, found {}", expected, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(schedule: &Schedule) -> Vec<(String, String)> {
+        schedule.entries.iter()
+            .map(|entry| (entry.start.format("%H:%M").to_string(), entry.end.format("%H:%M").to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_split_description_and_times() {
+        let raw = "
+                                        Vid pizzerian. Tisdag 6 oktober 19-19.45.
+
+                                    ";
+        let schedule = parse_schedule(raw).unwrap();
+        assert_eq!(Some("vid pizzerian".to_owned()), schedule.description);
+        assert_eq!(1, schedule.entries.len());
+        assert_eq!(Weekday::Tue, schedule.entries[0].weekday.recognized.value);
+        assert_eq!(6, schedule.entries[0].day);
+        assert_eq!(10, schedule.entries[0].month.recognized.value);
+        assert_eq!(vec![("19:00".to_owned(), "19:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_parse_schedule_without_description() {
+        let schedule = parse_schedule("onsdag 16 september 17.35-17.55").unwrap();
+        assert_eq!(None, schedule.description);
+        assert_eq!(1, schedule.entries.len());
+    }
+
+    #[test]
+    fn should_split_description_and_times_with_multiple_events() {
+        let schedule = parse_schedule("På parkeringen. Torsdag 17 september 18.45-19.05 och torsdag 29 oktober 18.45-19.05.").unwrap();
+        assert_eq!(Some("på parkeringen".to_owned()), schedule.description);
+        assert_eq!(2, schedule.entries.len());
+        assert_eq!(29, schedule.entries[1].day);
+    }
+
+    #[test]
+    fn should_split_description_and_times_with_tisdag_misspelled() {
+        let schedule = parse_schedule("på parkeringen kringlekullen. tisadg 1 september 18-18.45").unwrap();
+        assert_eq!(Some("på parkeringen kringlekullen".to_owned()), schedule.description);
+        assert_eq!("tisadg", schedule.entries[0].weekday.raw);
+        assert_eq!(Weekday::Tue, schedule.entries[0].weekday.recognized.value);
+    }
+
+    #[test]
+    fn should_split_description_and_times_with_onsdag_misspelled() {
+        let schedule = parse_schedule("vid återvinningsstationen. onsadg 22 september, 18.45-19.05, och onsdag 3 november, 18.45-19.05").unwrap();
+        assert_eq!(Some("vid återvinningsstationen".to_owned()), schedule.description);
+        assert_eq!(2, schedule.entries.len());
+        assert_eq!(Weekday::Wed, schedule.entries[0].weekday.recognized.value);
+    }
+
+    #[test]
+    fn should_error_on_unknown_bad_day_name() {
+        let result = parse_schedule("på parkeringen. xyzdag 1 september 18-18.45 och torsdag 21 september 19-19.30");
+        assert_eq!(ScheduleErrorKind::MissingDates, result.unwrap_err().kind);
+    }
+
+    #[test]
+    fn should_error_when_no_dates_are_found() {
+        let result = parse_schedule("på parkeringen vid skolan.");
+        assert_eq!(ScheduleErrorKind::MissingDates, result.unwrap_err().kind);
+    }
+
+    #[test]
+    fn should_split_description_and_times_with_och_in_description() {
+        let schedule = parse_schedule("vid ica gunnilse och återvinningsstationen. onsdag 16 september 17.35-17.55 och onsdag 28 oktober 17-17.20.").unwrap();
+        assert_eq!(Some("vid ica gunnilse och återvinningsstationen".to_owned()), schedule.description);
+        assert_eq!(vec![("17:35".to_owned(), "17:55".to_owned()), ("17:00".to_owned(), "17:20".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_handle_some_special_chars_in_description() {
+        let schedule = parse_schedule("vid se-banken (seb). onsdag 7 april 19-19.45 och tisdag 25 maj 19-19.45.").unwrap();
+        assert_eq!(Some("vid se-banken (seb)".to_owned()), schedule.description);
+        assert_eq!(2, schedule.entries.len());
+    }
+
+    #[test]
+    fn should_handle_kl_prefix_and_hour_without_minutes() {
+        let schedule = parse_schedule("tisdag 6 oktober kl. 18-18.45").unwrap();
+        assert_eq!(vec![("18:00".to_owned(), "18:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_handle_colon_and_en_dash() {
+        let schedule = parse_schedule("tisdag 6 oktober 18:00–18:45").unwrap();
+        assert_eq!(vec![("18:00".to_owned(), "18:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_share_time_range_between_dates() {
+        let schedule = parse_schedule("Vid skolan. Måndag 5 oktober, tisdag 6 oktober samt onsdag 7 oktober 18-18.45.").unwrap();
+        assert_eq!(3, schedule.entries.len());
+        assert_eq!(vec![("18:00".to_owned(), "18:45".to_owned()); 3], times(&schedule));
+        assert_eq!(7, schedule.entries[2].day);
+    }
+
+    #[test]
+    fn should_error_on_date_without_time() {
+        let result = parse_schedule("tisdag 6 oktober 18-18.45 och onsdag 7 oktober");
+        assert_eq!(ScheduleErrorKind::Syntax, result.unwrap_err().kind);
+    }
+
+    #[test]
+    fn should_error_when_end_is_before_start() {
+        let result = parse_schedule("tisdag 6 oktober 18.45-18");
+        assert_eq!(ScheduleErrorKind::Syntax, result.unwrap_err().kind);
+    }
+
+    fn statuses(schedule: &Schedule) -> Vec<EventStatus> {
+        schedule.entries.iter()
+            .map(|entry| entry.status)
+            .collect()
+    }
+
+    #[test]
+    fn should_mark_entries_as_scheduled() {
+        let schedule = parse_schedule("tisdag 6 oktober 18-18.45 och onsdag 7 oktober 18-18.45").unwrap();
+        assert_eq!(vec![EventStatus::Scheduled; 2], statuses(&schedule));
+    }
+
+    #[test]
+    fn should_recognise_cancelled_suffix() {
+        let schedule = parse_schedule("Vid skolan. Tisdag 6 oktober 18-18.45 inställt och onsdag 7 oktober 18-18.45.").unwrap();
+        assert_eq!(Some("vid skolan".to_owned()), schedule.description);
+        assert_eq!(vec![EventStatus::Cancelled, EventStatus::Scheduled], statuses(&schedule));
+    }
+
+    #[test]
+    fn should_recognise_cancelled_prefix() {
+        let schedule = parse_schedule("Vid skolan. Inställt: tisdag 6 oktober 18-18.45.").unwrap();
+        assert_eq!(Some("vid skolan".to_owned()), schedule.description);
+        assert_eq!(vec![EventStatus::Cancelled], statuses(&schedule));
+    }
+
+    #[test]
+    fn should_recognise_move_to_another_date() {
+        let schedule = parse_schedule("tisdag 6 oktober 18-18.45 flyttad till onsdag 7 oktober 19-19.45").unwrap();
+        assert_eq!(vec![EventStatus::Cancelled, EventStatus::Moved], statuses(&schedule));
+        assert_eq!(7, schedule.entries[1].day);
+        assert_eq!(vec![("18:00".to_owned(), "18:45".to_owned()), ("19:00".to_owned(), "19:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_recognise_move_to_another_time() {
+        let schedule = parse_schedule("tisdag 6 oktober 18-18.45 (flyttad till 19-19.45).").unwrap();
+        assert_eq!(vec![EventStatus::Moved], statuses(&schedule));
+        assert_eq!(vec![("19:00".to_owned(), "19:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_recognise_new_time() {
+        let schedule = parse_schedule("tisdag 6 oktober ny tid 19-19.45").unwrap();
+        assert_eq!(vec![EventStatus::Moved], statuses(&schedule));
+        assert_eq!(vec![("19:00".to_owned(), "19:45".to_owned())], times(&schedule));
+    }
+
+    #[test]
+    fn should_error_on_move_without_destination() {
+        let result = parse_schedule("tisdag 6 oktober 18-18.45 flyttad");
+        assert_eq!(ScheduleErrorKind::Syntax, result.unwrap_err().kind);
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use crate::city_profile::SourceProfile;
+use crate::scraper::page_fetcher::{self, FetchConfig, FetchOutcome, PageFetcherError};
+use crate::scraper::page_parser::{self, ParsedPage, PageParserError};
+use crate::scraper::page_source::{PageSource, Validators};
+
+/// One of the city's mobile collection services, e.g. the Farligt Avfall-bilen. Bundles where the schedule pages are fetched from
+/// with how they are parsed, so that the scraper can ingest several services the same way.
+#[async_trait]
+pub trait ScheduleSource {
+    /// Namespace for the location ids of all events from this source. Also used to keep archived pages of sources apart.
+    fn service_id(&self) -> &str;
+
+    /// Fetches all schedule pages, unless `previous` validators show that none of them have changed.
+    async fn fetch_pages(&self, config: &FetchConfig, previous: &BTreeMap<String, Validators>) -> Result<FetchOutcome, PageFetcherError>;
+
+    fn parse_page(&self, page: Vec<u8>, page_index: usize, scrape_date: NaiveDate) -> Result<ParsedPage, PageParserError>;
+}
+
+/// A paginated schedule of c-snippet elements, as published on goteborg.se for the Farligt Avfall-bilen.
+pub struct GoteborgSnippetsSource<S: PageSource> {
+    pages: S,
+    service_id: String,
+    main_path: String,
+    time_zone: Tz,
+}
+
+impl<S: PageSource> GoteborgSnippetsSource<S> {
+    pub fn new(pages: S, profile: &SourceProfile, time_zone: Tz) -> Self {
+        GoteborgSnippetsSource{
+            pages,
+            service_id: profile.service_id.clone(),
+            main_path: profile.path.clone(),
+            time_zone,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: PageSource + Sync> ScheduleSource for GoteborgSnippetsSource<S> {
+    fn service_id(&self) -> &str {
+        &self.service_id
+    }
+
+    async fn fetch_pages(&self, config: &FetchConfig, previous: &BTreeMap<String, Validators>) -> Result<FetchOutcome, PageFetcherError> {
+        page_fetcher::obtain_pages_if_modified(&self.pages, &self.main_path, config, previous).await
+    }
+
+    fn parse_page(&self, page: Vec<u8>, page_index: usize, scrape_date: NaiveDate) -> Result<ParsedPage, PageParserError> {
+        page_parser::parse_page(&self.service_id, self.time_zone, page, page_index, scrape_date)
+    }
+}
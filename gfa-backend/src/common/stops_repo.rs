@@ -1,12 +1,19 @@
-use std::{fmt, error, collections::HashMap};
-use rusoto_dynamodb::{DynamoDb, DynamoDbClient, QueryInput, ScanInput, AttributeValue};
+use std::{fmt, error, collections::{BTreeMap, HashMap}, sync::Mutex};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use rusoto_dynamodb::{DynamoDb, DynamoDbClient, QueryInput, ScanInput, GetItemInput, WriteRequest, PutRequest, AttributeValue};
 use rusoto_core::{Region};
 use log::{self, warn};
 use crate::pickup_stop::PickUpStop;
-use crate::dynamodb_util::MalformedDynamoDbResponse;
+use crate::pickup_event::{LocationAlias, service_of};
+use crate::dynamodb_util::{self, Item, item_to_coordinate};
+use crate::events_repo::write_in_batches;
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+/// Index of the aliases table, by the current location id which the aliases point to.
+const ALIAS_INDEX: &str = "byLocationId";
+
 #[derive(Debug)]
 struct MalformedStop {
     location_id: String,
@@ -18,65 +25,224 @@ impl fmt::Display for MalformedStop {
 }
 impl error::Error for MalformedStop {}
 
+#[derive(Debug)]
+struct UnprocessedAliases {
+    count: usize,
+}
+impl fmt::Display for UnprocessedAliases {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} location aliases were still unprocessed at the deadline", self.count)
+  }
+}
+impl error::Error for UnprocessedAliases {}
+
 
-pub async fn get_all_stops(table: &str, region: &Region, location_index: &str) -> Result<Vec<PickUpStop>, Error> {
-    let client = DynamoDbClient::new(region.clone());
-    let output = match client.scan(ScanInput{
-        table_name: table.to_owned(),
-        index_name: Some(location_index.to_owned()),
-        ..Default::default()
-    }).await {
-        Ok(output) => output,
-        Err(error) => return Err(Box::new(error)) 
-    };
-    let items = match output.items {
-        Some(items) => items,
-        None => return Err(Box::new(MalformedDynamoDbResponse))
-    };
-    let mut stops = items.iter()
-        .map(|item| item_to_stop(item))
-        .filter_map(|optional_stop| {
-            if optional_stop.is_none() {
-                warn!("Found malformed stop");
-                return None;
-            }
-            Some(optional_stop.unwrap())
-        })
-        .collect::<Vec<PickUpStop>>();
-    stops.sort();
-    stops.dedup();
-    Ok(stops)
+#[async_trait]
+pub trait StopsRepository: Send + Sync {
+    async fn get_all_stops(&self) -> Result<Vec<PickUpStop>, Error>;
+    /// Looks up a stop by its current id, or by any id it has had before.
+    async fn get_single_stop(&self, location_id: &str) -> Result<Option<PickUpStop>, Error>;
+    /// Returns the current id of a location, which is the given id unless it is an alias.
+    async fn resolve_location_id(&self, location_id: &str) -> Result<String, Error>;
+    /// Returns every id which points to the given location id, e.g. so that subscriptions made with an older id can be found.
+    async fn get_aliases(&self, location_id: &str) -> Result<Vec<String>, Error>;
+    /// Writes aliases, replacing any earlier target of the same alias ids. Unprocessed writes are retried, and it is an error
+    /// when some are still unprocessed at the deadline, since subscriptions made with those ids would be lost.
+    async fn store_aliases(&self, aliases: &[LocationAlias]) -> Result<(), Error>;
+}
+
+/// Stops are read from the location index of the events table, aliases from their own table.
+pub struct DynamoDbStopsRepository {
+    client: DynamoDbClient,
+    events_table: String,
+    location_index: String,
+    aliases_table: String,
+}
+
+impl DynamoDbStopsRepository {
+    pub fn new(region: &Region, events_table: &str, location_index: &str, aliases_table: &str) -> Self {
+        DynamoDbStopsRepository{
+            client: dynamodb_util::client(region),
+            events_table: events_table.to_owned(),
+            location_index: location_index.to_owned(),
+            aliases_table: aliases_table.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl StopsRepository for DynamoDbStopsRepository {
+    async fn get_all_stops(&self) -> Result<Vec<PickUpStop>, Error> {
+        let items: Vec<Item> = match dynamodb_util::scan(&self.client, ScanInput{
+            table_name: self.events_table.clone(),
+            index_name: Some(self.location_index.clone()),
+            ..Default::default()
+        }).try_collect().await {
+            Ok(items) => items,
+            Err(error) => return Err(Box::new(error)) 
+        };
+        let mut stops = items.iter()
+            .map(|item| item_to_stop(item))
+            .filter_map(|optional_stop| {
+                if optional_stop.is_none() {
+                    warn!("Found malformed stop");
+                    return None;
+                }
+                Some(optional_stop.unwrap())
+            })
+            .collect::<Vec<PickUpStop>>();
+        stops.sort();
+        stops.dedup();
+        Ok(stops)
+    }
+
+    async fn get_single_stop(&self, location_id: &str) -> Result<Option<PickUpStop>, Error> {
+        let location_id = &self.resolve_location_id(location_id).await?;
+        // Only the first page is read, since one event is enough to describe the stop
+        let items = dynamodb_util::query(&self.client, QueryInput{
+            table_name: self.events_table.clone(),
+            index_name: Some(self.location_index.clone()),
+            expression_attribute_values: Some([(":locationId".to_owned(), AttributeValue{s: Some(location_id.to_owned()), ..Default::default()})]
+                .iter()
+                .cloned()
+                .collect()),
+            key_condition_expression: Some("location_id = :locationId".to_owned()),
+            limit: Some(1),
+            ..Default::default()
+        });
+        futures::pin_mut!(items);
+        let item = match items.try_next().await {
+            Ok(Some(item)) => item,
+            Ok(None) => return Ok(None),
+            Err(error) => return Err(Box::new(error))
+        };
+        match item_to_stop(&item) {
+            Some(stop) => Ok(Some(stop)),
+            None => Err(Box::new(MalformedStop{
+                location_id: location_id.to_owned()
+            }))
+        }
+    }
+
+    async fn resolve_location_id(&self, location_id: &str) -> Result<String, Error> {
+        let output = match self.client.get_item(GetItemInput{
+            table_name: self.aliases_table.clone(),
+            key: [("alias_id".to_owned(), AttributeValue{s: Some(location_id.to_owned()), ..Default::default()})]
+                .iter()
+                .cloned()
+                .collect(),
+            ..Default::default()
+        }).await {
+            Ok(output) => output,
+            Err(error) => return Err(Box::new(error))
+        };
+        match output.item {
+            Some(item) => match item.get("location_id").and_then(|location_id| location_id.s.as_ref()) {
+                Some(current_id) => Ok(current_id.clone()),
+                None => Err(Box::new(MalformedStop{
+                    location_id: location_id.to_owned()
+                }))
+            },
+            None => Ok(location_id.to_owned()),
+        }
+    }
+
+    async fn get_aliases(&self, location_id: &str) -> Result<Vec<String>, Error> {
+        let items: Vec<Item> = match dynamodb_util::query(&self.client, QueryInput{
+            table_name: self.aliases_table.clone(),
+            index_name: Some(ALIAS_INDEX.to_owned()),
+            expression_attribute_values: Some([(":locationId".to_owned(), AttributeValue{s: Some(location_id.to_owned()), ..Default::default()})]
+                .iter()
+                .cloned()
+                .collect()),
+            key_condition_expression: Some("location_id = :locationId".to_owned()),
+            ..Default::default()
+        }).try_collect().await {
+            Ok(items) => items,
+            Err(error) => return Err(Box::new(error))
+        };
+        Ok(items.iter()
+            .filter_map(|item| item.get("alias_id")?.s.clone())
+            .collect())
+    }
+
+    async fn store_aliases(&self, aliases: &[LocationAlias]) -> Result<(), Error> {
+        let write_requests: Vec<WriteRequest> = aliases.iter()
+            .map(|alias| WriteRequest{
+                put_request: Some(PutRequest{
+                    item: [
+                        ("alias_id".to_owned(), AttributeValue{s: Some(alias.alias_id.clone()), ..Default::default()}),
+                        ("location_id".to_owned(), AttributeValue{s: Some(alias.location_id.clone()), ..Default::default()}),
+                    ].iter().cloned().collect()
+                }),
+                ..Default::default()
+            })
+            .collect();
+        let outcome = match write_in_batches(&self.client, &self.aliases_table, write_requests).await {
+            Ok(outcome) => outcome,
+            Err(error) => return Err(Box::new(error))
+        };
+        if outcome.written < aliases.len() {
+            return Err(Box::new(UnprocessedAliases{
+                count: aliases.len() - outcome.written,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps stops and aliases in memory, for tests and local runs.
+#[derive(Default)]
+pub struct InMemoryStopsRepository {
+    stops: Vec<PickUpStop>,
+    /// Current location ids by alias id.
+    aliases: Mutex<BTreeMap<String, String>>,
 }
 
-pub async fn get_single_stop(table: &str, region: &Region, location_index: &str, location_id: &str) -> Result<Option<PickUpStop>, Error> {
-    let client = DynamoDbClient::new(region.clone());
-    let output = match client.query(QueryInput{
-        table_name: table.to_owned(),
-        index_name: Some(location_index.to_owned()),
-        expression_attribute_values: Some([(":locationId".to_owned(), AttributeValue{s: Some(location_id.to_owned()), ..Default::default()})]
-            .iter()
+impl InMemoryStopsRepository {
+    pub fn with_stops(mut stops: Vec<PickUpStop>) -> Self {
+        stops.sort();
+        stops.dedup();
+        InMemoryStopsRepository{
+            stops,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl StopsRepository for InMemoryStopsRepository {
+    async fn get_all_stops(&self) -> Result<Vec<PickUpStop>, Error> {
+        Ok(self.stops.clone())
+    }
+
+    async fn get_single_stop(&self, location_id: &str) -> Result<Option<PickUpStop>, Error> {
+        let location_id = self.resolve_location_id(location_id).await?;
+        Ok(self.stops.iter()
+            .find(|stop| stop.location_id == location_id)
+            .cloned())
+    }
+
+    async fn resolve_location_id(&self, location_id: &str) -> Result<String, Error> {
+        Ok(self.aliases.lock().unwrap()
+            .get(location_id)
             .cloned()
-            .collect()),
-        key_condition_expression: Some("location_id = :locationId".to_owned()),
-        limit: Some(1),
-        ..Default::default()
-    }).await {
-        Ok(output) => output,
-        Err(error) => return Err(Box::new(error))
-    };
-    let items = match output.items {
-        Some(items) => items,
-        None => return Err(Box::new(MalformedDynamoDbResponse))
-    };
-    if items.is_empty() {
-        return Ok(None);
+            .unwrap_or_else(|| location_id.to_owned()))
     }
-    let item = items.first().unwrap();
-    match item_to_stop(item) {
-        Some(stop) => Ok(Some(stop)),
-        None => Err(Box::new(MalformedStop{
-            location_id: location_id.to_owned()
-        }))
+
+    async fn get_aliases(&self, location_id: &str) -> Result<Vec<String>, Error> {
+        Ok(self.aliases.lock().unwrap().iter()
+            .filter(|(_, current_id)| *current_id == location_id)
+            .map(|(alias_id, _)| alias_id.clone())
+            .collect())
+    }
+
+    async fn store_aliases(&self, aliases: &[LocationAlias]) -> Result<(), Error> {
+        let mut stored_aliases = self.aliases.lock().unwrap();
+        for alias in aliases {
+            stored_aliases.insert(alias.alias_id.clone(), alias.location_id.clone());
+        }
+        Ok(())
     }
 }
 
@@ -84,14 +250,21 @@ fn item_to_stop(item: &HashMap<String, AttributeValue>) -> Option<PickUpStop> {
     let location_id = item.get("location_id")?.s.as_ref()?;
     let street = item.get("street")?.s.as_ref()?;
     let district = item.get("district")?.s.as_ref()?;
+    let organisation_type = match item.get("organisation_type") {
+        None => None,
+        Some(organisation_type) => Some(organisation_type.s.as_ref()?.clone())
+    };
     let description = match item.get("description") {
         None => None,
         Some(description) => Some(description.s.as_ref()?.clone())
     };
     Some(PickUpStop{
+        service_id: service_of(location_id).to_owned(),
         location_id: location_id.clone(),
         street: street.clone(),
         district: district.clone(),
+        organisation_type,
         description,
+        coordinate: item_to_coordinate(item),
     })
 }
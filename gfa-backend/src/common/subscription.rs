@@ -3,7 +3,7 @@ use sha2::{Sha512, Digest};
 use rand::prelude::*;
 use chrono::{Duration, Utc};
 
-#[derive(fmt::Debug)]
+#[derive(fmt::Debug, Clone, PartialEq)]
 pub struct Subscription {
     pub email: String,
     pub location_id: String,
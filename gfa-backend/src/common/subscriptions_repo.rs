@@ -1,9 +1,11 @@
-use std::{error, fmt, collections::HashMap};
+use std::{error, fmt, collections::{BTreeMap, HashMap}, sync::Mutex};
+use async_trait::async_trait;
+use futures::TryStreamExt;
 use rusoto_dynamodb::{DynamoDb, DynamoDbClient, GetItemInput, PutItemInput, QueryInput, DeleteItemInput, AttributeValue};
 use rusoto_core::{Region};
 use log::{self, warn};
 use crate::subscription::Subscription;
-use crate::dynamodb_util::MalformedDynamoDbResponse;
+use crate::dynamodb_util::{self, Item};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -35,195 +37,271 @@ impl fmt::Display for TokenCollision {
 }
 impl error::Error for TokenCollision {}
 
-pub async fn store_subscription(table: &str, region: &Region, subscription: &Subscription) -> Result<(), Error> {
-    let client = DynamoDbClient::new(region.clone());
-
-    let mut attributes: HashMap<String, AttributeValue> = HashMap::new(); 
-    attributes.insert("email".to_owned(), AttributeValue{
-        s: Some(subscription.email.clone()),
-        ..Default::default()
-    });
-    attributes.insert("location_id".to_owned(), AttributeValue{
-        s: Some(subscription.location_id.clone()),
-        ..Default::default()
-    });
-    if subscription.auth_token.is_some() {
-        attributes.insert("auth_token".to_owned(), AttributeValue{
-            s: Some(subscription.auth_token.as_ref().unwrap().clone()),
+#[async_trait]
+pub trait SubscriptionsRepository: Send + Sync {
+    async fn store_subscription(&self, subscription: &Subscription) -> Result<(), Error>;
+    async fn get_subscription(&self, email: &str, location_id: &str) -> Result<Option<Subscription>, Error>;
+    async fn get_subscription_by_unsubscribe_token(&self, unsubscribe_token: &str) -> Result<Option<Subscription>, Error>;
+    async fn get_subscription_by_auth_token(&self, auth_token: &str) -> Result<Option<Subscription>, Error>;
+    async fn get_authenticated_subscriptions(&self, location_id: &str) -> Result<Vec<Subscription>, Error>;
+    async fn remove_subscription(&self, subscription: &Subscription) -> Result<(), Error>;
+}
+
+pub struct DynamoDbSubscriptionsRepository {
+    client: DynamoDbClient,
+    table: String,
+}
+
+impl DynamoDbSubscriptionsRepository {
+    pub fn new(region: &Region, table: &str) -> Self {
+        DynamoDbSubscriptionsRepository{
+            client: dynamodb_util::client(region),
+            table: table.to_owned(),
+        }
+    }
+
+    async fn get_subscription_by_token(&self, index_name: &str, property_name: &str, value: &str) -> Result<Option<Subscription>, Error> {
+        let mut attribute_values = HashMap::new();
+        attribute_values.insert(format!(":{}", property_name), AttributeValue{
+            s: Some(value.to_owned()),
             ..Default::default()
         });
+        match dynamodb_util::query(&self.client, QueryInput{
+            index_name: Some(index_name.to_owned()),
+            table_name: self.table.clone(),
+            expression_attribute_values: Some(attribute_values),
+            key_condition_expression: Some(format!("{} = :{}", property_name, property_name)),
+            ..Default::default()
+        }).try_collect::<Vec<Item>>().await {
+            Ok(items) => {
+                if items.is_empty() {
+                    return Ok(None)
+                }
+                if items.len() > 1 {
+                    return Err(Box::new(TokenCollision{}))
+                }
+                let item = items.first().unwrap();
+                match item_to_subscription(item) {
+                    Some(subscription) => Ok(Some(subscription)),
+                    None => {
+                        Err(Box::new(MalformedSubscription{
+                            email: None,
+                            token: Some(value.to_owned())
+                        }))
+                    }
+                }
+
+            },
+            Err(error) => {
+                Err(Box::new(error))
+            }
+        }
     }
-    if subscription.unsubscribe_token.is_some() {
-        attributes.insert("unsubscribe_token".to_owned(), AttributeValue{
-            s: Some(subscription.unsubscribe_token.as_ref().unwrap().clone()),
+}
+
+#[async_trait]
+impl SubscriptionsRepository for DynamoDbSubscriptionsRepository {
+    async fn store_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        let mut attributes: HashMap<String, AttributeValue> = HashMap::new(); 
+        attributes.insert("email".to_owned(), AttributeValue{
+            s: Some(subscription.email.clone()),
             ..Default::default()
         });
-    }
-    attributes.insert("is_authenticated".to_owned(), AttributeValue{
-        bool: Some(subscription.is_authenticated),
-        ..Default::default()
-    });
-    if subscription.ttl.is_some() {
-        attributes.insert("ttl".to_owned(), AttributeValue{
-            n: Some(subscription.ttl.unwrap().to_string()),
+        attributes.insert("location_id".to_owned(), AttributeValue{
+            s: Some(subscription.location_id.clone()),
             ..Default::default()
         });
-    }
+        if subscription.auth_token.is_some() {
+            attributes.insert("auth_token".to_owned(), AttributeValue{
+                s: Some(subscription.auth_token.as_ref().unwrap().clone()),
+                ..Default::default()
+            });
+        }
+        if subscription.unsubscribe_token.is_some() {
+            attributes.insert("unsubscribe_token".to_owned(), AttributeValue{
+                s: Some(subscription.unsubscribe_token.as_ref().unwrap().clone()),
+                ..Default::default()
+            });
+        }
+        attributes.insert("is_authenticated".to_owned(), AttributeValue{
+            bool: Some(subscription.is_authenticated),
+            ..Default::default()
+        });
+        if subscription.ttl.is_some() {
+            attributes.insert("ttl".to_owned(), AttributeValue{
+                n: Some(subscription.ttl.unwrap().to_string()),
+                ..Default::default()
+            });
+        }
 
-    match client.put_item(PutItemInput{
-        item: attributes,
-        table_name: table.to_owned(),
-        ..Default::default()
-    }).await {
-        Ok(_response) => {
-            Ok(())
-        },
-        Err(err) => {
-            Err(Box::new(err))
+        match self.client.put_item(PutItemInput{
+            item: attributes,
+            table_name: self.table.clone(),
+            ..Default::default()
+        }).await {
+            Ok(_response) => {
+                Ok(())
+            },
+            Err(err) => {
+                Err(Box::new(err))
+            }
         }
     }
-}
 
-pub async fn get_subscription(table: &str, region: &Region, email: &str, location_id: &str) -> Result<Option<Subscription>, Error> {
-    let client = DynamoDbClient::new(region.clone());
-
-    let mut attributes: HashMap<String, AttributeValue> = HashMap::new();
-    attributes.insert("email".to_owned(), AttributeValue{
-        s: Some(email.to_owned()),
-        ..Default::default()
-    });
-    attributes.insert("location_id".to_owned(), AttributeValue{
-        s: Some(location_id.to_owned()),
-        ..Default::default()
-    });
-
-    match client.get_item(GetItemInput{
-        table_name: table.to_owned(),
-        key: attributes,
-        ..Default::default()
-    }).await {
-        Ok(response) => {
-            let item = match response.item {
-                Some(item) => item,
-                None => {
-                    return Ok(None)
-                }
-            };
-            match item_to_subscription(&item) {
-                Some(subscription) => Ok(Some(subscription)),
-                None => {
-                    Err(Box::new(MalformedSubscription{
-                        email: Some(email.to_owned()),
-                        token: None,
-                    }))
+    async fn get_subscription(&self, email: &str, location_id: &str) -> Result<Option<Subscription>, Error> {
+        let mut attributes: HashMap<String, AttributeValue> = HashMap::new();
+        attributes.insert("email".to_owned(), AttributeValue{
+            s: Some(email.to_owned()),
+            ..Default::default()
+        });
+        attributes.insert("location_id".to_owned(), AttributeValue{
+            s: Some(location_id.to_owned()),
+            ..Default::default()
+        });
+
+        match self.client.get_item(GetItemInput{
+            table_name: self.table.clone(),
+            key: attributes,
+            ..Default::default()
+        }).await {
+            Ok(response) => {
+                let item = match response.item {
+                    Some(item) => item,
+                    None => {
+                        return Ok(None)
+                    }
+                };
+                match item_to_subscription(&item) {
+                    Some(subscription) => Ok(Some(subscription)),
+                    None => {
+                        Err(Box::new(MalformedSubscription{
+                            email: Some(email.to_owned()),
+                            token: None,
+                        }))
+                    }
                 }
+            },
+            Err(err) => {
+                Err(Box::new(err))
             }
-        },
-        Err(err) => {
-            Err(Box::new(err))
         }
     }
-}
 
-pub async fn get_subscription_by_unsubscribe_token(table: &str, region: &Region, unsubscribe_token: &str) -> Result<Option<Subscription>, Error> {
-    get_subscription_by_token(table, region, "byUnsubscribeToken", "unsubscribe_token", unsubscribe_token).await
-}
+    async fn get_subscription_by_unsubscribe_token(&self, unsubscribe_token: &str) -> Result<Option<Subscription>, Error> {
+        self.get_subscription_by_token("byUnsubscribeToken", "unsubscribe_token", unsubscribe_token).await
+    }
 
-pub async fn get_subscription_by_auth_token(table: &str, region: &Region, auth_token: &str) -> Result<Option<Subscription>, Error> {
-    get_subscription_by_token(table, region, "byAuthToken", "auth_token", auth_token).await
-}
+    async fn get_subscription_by_auth_token(&self, auth_token: &str) -> Result<Option<Subscription>, Error> {
+        self.get_subscription_by_token("byAuthToken", "auth_token", auth_token).await
+    }
 
-async fn get_subscription_by_token(table: &str, region: &Region, index_name: &str, property_name: &str, value: &str) -> Result<Option<Subscription>, Error> {
-    let client = DynamoDbClient::new(region.clone());
-    let mut attribute_values = HashMap::new();
-    attribute_values.insert(format!(":{}", property_name), AttributeValue{
-        s: Some(value.to_owned()),
-        ..Default::default()
-    });
-    match client.query(QueryInput{
-        index_name: Some(index_name.to_owned()),
-        table_name: table.to_owned(),
-        expression_attribute_values: Some(attribute_values),
-        key_condition_expression: Some(format!("{} = :{}", property_name, property_name)),
-        ..Default::default()
-    }).await {
-        Ok(response) => {
-            let items = match response.items {
-                Some(items) => items,
-                None => return Err(Box::new(MalformedDynamoDbResponse))
-            };
-            if items.is_empty() {
-                return Ok(None)
-            }
-            if items.len() > 1 {
-                return Err(Box::new(TokenCollision{}))
-            }
-            let item = items.first().unwrap();
-            match item_to_subscription(item) {
-                Some(subscription) => Ok(Some(subscription)),
-                None => {
-                    Err(Box::new(MalformedSubscription{
-                        email: None,
-                        token: Some(value.to_owned())
-                    }))
-                }
+    async fn get_authenticated_subscriptions(&self, location_id: &str) -> Result<Vec<Subscription>, Error> {
+        let mut attribute_values = HashMap::new();
+        attribute_values.insert(":locationId".to_owned(), AttributeValue{
+            s: Some(location_id.to_owned()),
+            ..Default::default()
+        });
+        match dynamodb_util::query(&self.client, QueryInput{
+            index_name: Some("byLocationId".to_owned()),
+            table_name: self.table.clone(),
+            expression_attribute_values: Some(attribute_values),
+            key_condition_expression: Some("location_id = :locationId".to_owned()),
+            ..Default::default()
+        }).try_collect::<Vec<Item>>().await {
+            Ok(items) => {
+               Ok(items.iter()
+                    .filter_map(|item| match item_to_subscription(item) {
+                        Some(subscription) => Some(subscription),
+                        None => {
+                            warn!("Found malformed subscription: {:?}", item);
+                            None
+                        }
+                    })
+                    .filter(|subscription| subscription.is_authenticated)
+                    .collect())
+            },
+            Err(error) => {
+                Err(Box::new(error))
             }
+        }
+    }
 
-        },
-        Err(error) => {
-            Err(Box::new(error))
+    async fn remove_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        match self.client.delete_item(DeleteItemInput{
+            table_name: self.table.clone(),
+            key: [
+                ("email".to_owned(), AttributeValue{s: Some(subscription.email.to_owned()), ..Default::default()}),
+                ("location_id".to_owned(), AttributeValue{s: Some(subscription.location_id.to_owned()), ..Default::default()})
+            ].iter().cloned().collect(),
+            ..Default::default()
+        }).await {
+            Ok(_output) => Ok(()),
+            Err(error) => Err(Box::new(error))
         }
     }
 }
 
-pub async fn get_authenticated_subscriptions(table: &str, region: &Region, location_id: &str) -> Result<Vec<Subscription>, Error> {
-    let client = DynamoDbClient::new(region.clone());
-    let mut attribute_values = HashMap::new();
-    attribute_values.insert(":locationId".to_owned(), AttributeValue{
-        s: Some(location_id.to_owned()),
-        ..Default::default()
-    });
-    match client.query(QueryInput{
-        index_name: Some("byLocationId".to_owned()),
-        table_name: table.to_owned(),
-        expression_attribute_values: Some(attribute_values),
-        key_condition_expression: Some("location_id = :locationId".to_owned()),
-        ..Default::default()
-    }).await {
-        Ok(response) => {
-           let items = match response.items {
-               Some(items) => items,
-               None => return Err(Box::new(MalformedDynamoDbResponse))
-           };
-           Ok(items.iter()
-                .filter_map(|item| match item_to_subscription(item) {
-                    Some(subscription) => Some(subscription),
-                    None => {
-                        warn!("Found malformed subscription: {:?}", item);
-                        None
-                    }
-                })
-                .filter(|subscription| subscription.is_authenticated)
-                .collect())
-        },
-        Err(error) => {
-            Err(Box::new(error))
+/// Keeps subscriptions in memory, for tests and local runs.
+#[derive(Default)]
+pub struct InMemorySubscriptionsRepository {
+    /// Subscriptions by email and location id, which is the key of the subscriptions table.
+    subscriptions: Mutex<BTreeMap<(String, String), Subscription>>,
+}
+
+impl InMemorySubscriptionsRepository {
+    pub fn with_subscriptions(subscriptions: Vec<Subscription>) -> Self {
+        InMemorySubscriptionsRepository{
+            subscriptions: Mutex::new(subscriptions.into_iter()
+                .map(|subscription| ((subscription.email.clone(), subscription.location_id.clone()), subscription))
+                .collect()),
         }
     }
+
+    fn get_subscription_by_token(&self, token: &str, token_of: fn(&Subscription) -> Option<&String>) -> Result<Option<Subscription>, Error> {
+        let subscriptions: Vec<Subscription> = self.subscriptions.lock().unwrap().values()
+            .filter(|subscription| token_of(subscription).map(|t| t == token).unwrap_or(false))
+            .cloned()
+            .collect();
+        if subscriptions.len() > 1 {
+            return Err(Box::new(TokenCollision{}))
+        }
+        Ok(subscriptions.into_iter().next())
+    }
 }
 
-pub async fn remove_subscription(table: &str, region: &Region, subscription: &Subscription) -> Result<(), Error> {
-    let client = DynamoDbClient::new(region.clone());
-    match client.delete_item(DeleteItemInput{
-        table_name: table.to_owned(),
-        key: [
-            ("email".to_owned(), AttributeValue{s: Some(subscription.email.to_owned()), ..Default::default()}),
-            ("location_id".to_owned(), AttributeValue{s: Some(subscription.location_id.to_owned()), ..Default::default()})
-        ].iter().cloned().collect(),
-        ..Default::default()
-    }).await {
-        Ok(_output) => Ok(()),
-        Err(error) => Err(Box::new(error))
+#[async_trait]
+impl SubscriptionsRepository for InMemorySubscriptionsRepository {
+    async fn store_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        self.subscriptions.lock().unwrap()
+            .insert((subscription.email.clone(), subscription.location_id.clone()), subscription.clone());
+        Ok(())
+    }
+
+    async fn get_subscription(&self, email: &str, location_id: &str) -> Result<Option<Subscription>, Error> {
+        Ok(self.subscriptions.lock().unwrap()
+            .get(&(email.to_owned(), location_id.to_owned()))
+            .cloned())
+    }
+
+    async fn get_subscription_by_unsubscribe_token(&self, unsubscribe_token: &str) -> Result<Option<Subscription>, Error> {
+        self.get_subscription_by_token(unsubscribe_token, |subscription| subscription.unsubscribe_token.as_ref())
+    }
+
+    async fn get_subscription_by_auth_token(&self, auth_token: &str) -> Result<Option<Subscription>, Error> {
+        self.get_subscription_by_token(auth_token, |subscription| subscription.auth_token.as_ref())
+    }
+
+    async fn get_authenticated_subscriptions(&self, location_id: &str) -> Result<Vec<Subscription>, Error> {
+        Ok(self.subscriptions.lock().unwrap().values()
+            .filter(|subscription| subscription.location_id == location_id && subscription.is_authenticated)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        self.subscriptions.lock().unwrap()
+            .remove(&(subscription.email.clone(), subscription.location_id.clone()));
+        Ok(())
     }
 }
 
@@ -0,0 +1,69 @@
+//! Serde formats which keep times in the wire format the API and the stored events have always used.
+
+/// A `DateTime<Utc>` as RFC 3339 with a numeric offset, e.g. "2020-09-23T16:00:00+00:00". Any offset is accepted when reading.
+pub mod rfc3339 {
+    use chrono::{DateTime, Utc};
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&time)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// A `NaiveDate` as YYYY-MM-DD.
+pub mod iso_date {
+    use chrono::NaiveDate;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub const FORMAT: &str = "%Y-%m-%d";
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+        let date = String::deserialize(deserializer)?;
+        NaiveDate::parse_from_str(&date, FORMAT).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Times {
+        #[serde(with = "super::rfc3339")]
+        time: DateTime<Utc>,
+        #[serde(with = "super::iso_date")]
+        date: NaiveDate,
+    }
+
+    #[test]
+    fn should_keep_wire_format() {
+        let times = Times{
+            time: Utc.ymd(2020, 9, 23).and_hms(16, 0, 0),
+            date: NaiveDate::from_ymd(2020, 9, 23),
+        };
+        assert_eq!(r#"{"time":"2020-09-23T16:00:00+00:00","date":"2020-09-23"}"#, serde_json::to_string(&times).unwrap());
+    }
+
+    #[test]
+    fn should_read_any_offset() {
+        let times: Times = serde_json::from_str(r#"{"time":"2020-09-23T18:00:00+02:00","date":"2020-09-23"}"#).unwrap();
+        assert_eq!(Utc.ymd(2020, 9, 23).and_hms(16, 0, 0), times.time);
+    }
+
+    #[test]
+    fn should_reject_malformed_date() {
+        assert!(serde_json::from_str::<Times>(r#"{"time":"2020-09-23T18:00:00+02:00","date":"23/9 2020"}"#).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{env, str::FromStr, collections::HashMap};
+use lambda::{handler_fn, Context};
+use serde::Serialize;
+use simple_logger::{SimpleLogger};
+use log::{self, error, LevelFilter};
+use rusoto_core::{Region};
+use chrono::{DateTime, Duration, Utc};
+use aws_lambda_events::event::apigw::{ApiGatewayV2httpRequest, ApiGatewayV2httpResponse};
+use common::pickup_event::PickUpEvent;
+use common::pickup_stop::PickUpStop;
+use common::events_repo::{EventsRepository, DynamoDbEventsRepository};
+use common::stops_repo::{StopsRepository, DynamoDbStopsRepository};
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// The schedule is published half a year at a time, so this covers every visit which is known.
+const UPCOMING_WEEKS: i64 = 26;
+
+#[derive(Serialize)]
+struct StopWithVisits {
+    #[serde(flatten)]
+    stop: PickUpStop,
+    upcoming_visits: Vec<PickUpEvent>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let _log = SimpleLogger::new()
+        .with_level(LevelFilter::Info)
+        .init();
+    let handler = handler_fn(handle_request);
+    lambda::run(handler).await?;
+    Ok(())
+}
+
+async fn handle_request(event: ApiGatewayV2httpRequest, _: Context) -> Result<ApiGatewayV2httpResponse, Error> {
+    let aws_region = env::var("AWS_REGION")?;
+    let aws_region = Region::from_str(&aws_region)?;
+    let events_table = env::var("EVENTS_TABLE")?;
+    let location_index = env::var("LOCATION_INDEX")?;
+    let aliases_table = env::var("ALIASES_TABLE")?;
+
+    let location_id = match event.path_parameters.get("location_id") {
+        Some(location_id) => location_id,
+        None => {
+            return Ok(create_response("Missing location id".to_owned(), 400));
+        }
+    };
+
+    let stops = DynamoDbStopsRepository::new(&aws_region, &events_table, &location_index, &aliases_table);
+    let events = DynamoDbEventsRepository::new(&aws_region, &events_table, &location_index);
+    Ok(get_stop(&stops, &events, location_id, Utc::now()).await)
+}
+
+/// Returns the stop, also when it is asked for by an id it used to have, with the visits which have not yet ended.
+async fn get_stop(stops: &dyn StopsRepository, events: &dyn EventsRepository, location_id: &str, now: DateTime<Utc>) -> ApiGatewayV2httpResponse {
+    let stop = match stops.get_single_stop(location_id).await {
+        Ok(Some(stop)) => stop,
+        Ok(None) => {
+            return create_response(format!("Location does not exist: {}", location_id), 404)
+        },
+        Err(e) => {
+            error!("Failed to read stop {}: {}", location_id, e);
+            return create_response(format!("Failed to read stop: {}", e), 500)
+        }
+    };
+    let today = now.date().naive_utc();
+    let upcoming_visits = match events.get_by_location(&stop.location_id, today, today + Duration::weeks(UPCOMING_WEEKS)).await {
+        Ok(visits) => visits.into_iter()
+            .filter(|visit| visit.time_end > now)
+            .collect(),
+        Err(e) => {
+            error!("Failed to read visits of {}: {}", stop.location_id, e);
+            return create_response(format!("Failed to read visits: {}", e), 500)
+        }
+    };
+    let stop_json = match serde_json::to_string(&StopWithVisits{ stop, upcoming_visits }) {
+        Ok(json) => json,
+        Err(e) => {
+            return create_response(format!("Failed to serialize stop into json: {}", e), 500)
+        }
+    };
+    create_response(stop_json, 200)
+}
+
+fn create_response(body: String, status_code: i64) -> ApiGatewayV2httpResponse {
+    ApiGatewayV2httpResponse {
+        status_code,
+        headers: HashMap::new(),
+        multi_value_headers: HashMap::new(),
+        body: Some(body),
+        is_base64_encoded: Some(false),
+        cookies: Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use common::pickup_event::{LocationAlias, FARLIGT_AVFALL_SERVICE_ID, parse_time};
+    use common::events_repo::InMemoryEventsRepository;
+    use common::stops_repo::InMemoryStopsRepository;
+
+    fn visit(start: &str, end: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Storgatan".to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap()
+    }
+
+    fn stops() -> InMemoryStopsRepository {
+        InMemoryStopsRepository::with_stops(vec![
+            PickUpStop::new("farligt-avfall:centrum_storgatan".to_owned(), "Storgatan".to_owned(), "Centrum".to_owned(), None, None),
+        ])
+    }
+
+    #[tokio::test]
+    async fn should_return_stop_with_upcoming_visits() {
+        let events = InMemoryEventsRepository::with_events(vec![
+            visit("2020-09-01T16:00:00+00:00", "2020-09-01T16:45:00+00:00"),
+            visit("2020-09-23T08:00:00+00:00", "2020-09-23T08:45:00+00:00"),
+            visit("2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00"),
+            visit("2020-10-23T16:00:00+00:00", "2020-10-23T16:45:00+00:00"),
+        ]);
+        let now = parse_time("2020-09-23T12:00:00+00:00").unwrap();
+        let response = get_stop(&stops(), &events, "farligt-avfall:centrum_storgatan", now).await;
+        assert_eq!(200, response.status_code);
+        let body: Value = serde_json::from_str(&response.body.unwrap()).unwrap();
+        assert_eq!("Storgatan", body["street"]);
+        let starts: Vec<&str> = body["upcoming_visits"].as_array().unwrap().iter()
+            .map(|visit| visit["time_start"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["2020-09-23T16:00:00+00:00", "2020-10-23T16:00:00+00:00"], starts);
+    }
+
+    #[tokio::test]
+    async fn should_find_stop_by_previous_id() {
+        let stops = stops();
+        stops.store_aliases(&[LocationAlias{
+            alias_id: "centrum_storgatan".to_owned(),
+            location_id: "farligt-avfall:centrum_storgatan".to_owned(),
+        }]).await.unwrap();
+        let response = get_stop(&stops, &InMemoryEventsRepository::default(), "centrum_storgatan", Utc::now()).await;
+        assert_eq!(200, response.status_code);
+        assert!(response.body.unwrap().contains("\"location_id\":\"farligt-avfall:centrum_storgatan\""));
+    }
+
+    #[tokio::test]
+    async fn should_not_find_unknown_stop() {
+        let response = get_stop(&stops(), &InMemoryEventsRepository::default(), "farligt-avfall:centrum_okand", Utc::now()).await;
+        assert_eq!(404, response.status_code);
+    }
+}
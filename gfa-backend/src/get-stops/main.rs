@@ -5,7 +5,7 @@ use simple_logger::{SimpleLogger};
 use log::{self, LevelFilter};
 use rusoto_core::{Region};
 use aws_lambda_events::event::apigw::ApiGatewayV2httpResponse;
-use common::stops_repo::get_all_stops;
+use common::stops_repo::{StopsRepository, DynamoDbStopsRepository};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -39,20 +39,26 @@ async fn handle_request(_event: Value, _: Context) -> Result<ApiGatewayV2httpRes
     let aws_region = Region::from_str(&aws_region)?;
     let events_table = env::var("EVENTS_TABLE")?;
     let location_index = env::var("LOCATION_INDEX")?;
+    let aliases_table = env::var("ALIASES_TABLE")?;
 
-    let stops = match get_all_stops(&events_table, &aws_region, &location_index).await {
+    let stops = DynamoDbStopsRepository::new(&aws_region, &events_table, &location_index, &aliases_table);
+    Ok(get_stops(&stops).await)
+}
+
+async fn get_stops(stops: &dyn StopsRepository) -> ApiGatewayV2httpResponse {
+    let stops = match stops.get_all_stops().await {
         Ok(stops) => stops, 
         Err(e) => {
-            return Ok(create_response(format!("Failed to read stops: {}", e), 500))
+            return create_response(format!("Failed to read stops: {}", e), 500)
         }
     };
     let stops_json = match serde_json::to_string(&stops) {
         Ok(json) => json,
         Err(e) => {
-            return Ok(create_response(format!("Failed to serialize stops into json: {}", e), 500))
+            return create_response(format!("Failed to serialize stops into json: {}", e), 500)
         }
     };
-    Ok(create_response(stops_json, 200))
+    create_response(stops_json, 200)
 }
 
 fn create_response(body: String, status_code: i64) -> ApiGatewayV2httpResponse {
@@ -65,3 +71,20 @@ fn create_response(body: String, status_code: i64) -> ApiGatewayV2httpResponse {
         cookies: Vec::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_stop::PickUpStop;
+    use common::stops_repo::InMemoryStopsRepository;
+
+    #[tokio::test]
+    async fn should_return_all_stops() {
+        let stops = InMemoryStopsRepository::with_stops(vec![
+            PickUpStop::new("farligt-avfall:centrum_storgatan".to_owned(), "Storgatan".to_owned(), "Centrum".to_owned(), None, None),
+        ]);
+        let response = get_stops(&stops).await;
+        assert_eq!(200, response.status_code);
+        assert!(response.body.unwrap().contains("farligt-avfall:centrum_storgatan"));
+    }
+}
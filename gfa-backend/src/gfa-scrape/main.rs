@@ -0,0 +1,226 @@
+use std::{env, fs, io::{self, Read, Write}, collections::BTreeMap, path::{Path, PathBuf}, process, str::FromStr};
+use chrono::{NaiveDate, Utc};
+use common::city_profile::{CityProfile, SourceProfile};
+use common::pickup_event::PickUpEvent;
+use common::scraper::page_fetcher::{FetchConfig, FetchOutcome};
+use common::scraper::page_parser::{self, SnippetDiagnostic};
+use common::scraper::page_fingerprint::{self, PageFingerprint};
+use common::scraper::page_source::{PageSource, HttpPageSource, ReplayPageSource, RecordingPageSource};
+use common::scraper::schedule_source::{ScheduleSource, GoteborgSnippetsSource};
+use output::Format;
+
+mod output;
+
+type Error = Box<dyn std::error::Error>;
+
+const USAGE: &str = "Usage: gfa-scrape [OPTIONS]
+
+Scrapes the Farligt Avfall schedule and prints all pick-up events. The city is chosen
+with CITY_PROFILE or CITY_PROFILE_JSON, like in the lambdas, and defaults to Göteborg.
+
+Options:
+    --base-url URL     Fetch pages from URL instead of the city's website
+    --record DIR       Save fetched pages to DIR, so that they can be read again with --dir
+    --dir DIR          Read pages from DIR instead of the network. DIR is either a recording,
+                       or a directory of .html files such as an archived scraper run
+    --stdin            Read a single page from stdin instead of the network
+    --format FORMAT    Output format: json (default), csv or ndjson
+    --diagnostics      Print problems found in each snippet instead of events
+    --date YYYY-MM-DD  Date of the scrape, used to infer years (default: today)
+    --help             Print this message";
+
+#[derive(Debug, PartialEq)]
+enum Input {
+    Network { base_url: Option<String>, record: Option<PathBuf> },
+    Directory(PathBuf),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    input: Input,
+    format: Format,
+    diagnostics: bool,
+    scrape_date: Option<NaiveDate>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args).await {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Returns `None` if help was requested.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut base_url: Option<String> = None;
+    let mut record: Option<PathBuf> = None;
+    let mut directory: Option<PathBuf> = None;
+    let mut stdin = false;
+    let mut format = Format::Json;
+    let mut diagnostics = false;
+    let mut scrape_date: Option<NaiveDate> = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("Missing value for {}", name));
+        match arg.as_str() {
+            "--base-url" => base_url = Some(value("--base-url")?),
+            "--record" => record = Some(PathBuf::from(value("--record")?)),
+            "--dir" => directory = Some(PathBuf::from(value("--dir")?)),
+            "--stdin" => stdin = true,
+            "--format" => format = Format::from_str(&value("--format")?)?,
+            "--diagnostics" => diagnostics = true,
+            "--date" => scrape_date = Some(match NaiveDate::parse_from_str(&value("--date")?, "%Y-%m-%d") {
+                Ok(date) => date,
+                Err(e) => return Err(format!("Invalid --date: {}", e)),
+            }),
+            "--help" | "-h" => return Ok(None),
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    let input = match (directory, stdin) {
+        (Some(_), true) => return Err("--dir and --stdin cannot be combined".to_owned()),
+        (Some(_), false) | (None, true) if base_url.is_some() || record.is_some() => {
+            return Err("--base-url and --record can only be used when reading from the network".to_owned());
+        },
+        (Some(directory), false) => Input::Directory(directory),
+        (None, true) => Input::Stdin,
+        (None, false) => Input::Network{
+            base_url,
+            record,
+        },
+    };
+    Ok(Some(Args{
+        input,
+        format,
+        diagnostics,
+        scrape_date,
+    }))
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    let profile = CityProfile::from_env()?;
+    let source_profile = &profile.sources[0];
+    let config = FetchConfig::default();
+    let pages = match args.input {
+        Input::Network{ base_url, record } => {
+            let http = HttpPageSource::new(base_url.as_ref().unwrap_or(&source_profile.base_url), config.timeout)?;
+            match record {
+                Some(record) => fetch_pages(RecordingPageSource::new(http, record)?, source_profile, &profile, &config).await?,
+                None => fetch_pages(http, source_profile, &profile, &config).await?,
+            }
+        },
+        Input::Directory(directory) => read_directory(&directory, source_profile, &profile, &config).await?,
+        Input::Stdin => {
+            let mut page: Vec<u8> = Vec::new();
+            io::stdin().read_to_end(&mut page)?;
+            vec![page]
+        },
+    };
+    let scrape_date = args.scrape_date.unwrap_or_else(|| profile.local_date(Utc::now()));
+    let mut events: Vec<PickUpEvent> = Vec::new();
+    let mut diagnostics: Vec<SnippetDiagnostic> = Vec::new();
+    let mut fingerprints: Vec<PageFingerprint> = Vec::new();
+    let mut total_snippets = 0;
+    for (page_index, page) in pages.into_iter().enumerate() {
+        let mut parsed = page_parser::parse_page(&source_profile.service_id, profile.time_zone, page, page_index, scrape_date)?;
+        events.append(&mut parsed.events);
+        diagnostics.append(&mut parsed.diagnostics);
+        total_snippets += parsed.total_snippets;
+        fingerprints.push(parsed.fingerprint);
+    }
+    // Only a warning here, since single pages from --stdin or --dir never add up to the announced total
+    if let Err(e) = page_fingerprint::check_layout(&fingerprints, total_snippets) {
+        eprintln!("Warning: {}", e);
+    }
+    eprintln!("Found {} events and {} diagnostics in {} snippets", events.len(), diagnostics.len(), total_snippets);
+    let rendered = match args.diagnostics {
+        true => output::render(&diagnostics, args.format)?,
+        false => output::render(&events, args.format)?,
+    };
+    io::stdout().write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+async fn fetch_pages<S: PageSource + Sync>(pages: S, source_profile: &SourceProfile, profile: &CityProfile, config: &FetchConfig) -> Result<Vec<Vec<u8>>, Error> {
+    match GoteborgSnippetsSource::new(pages, source_profile, profile.time_zone).fetch_pages(config, &BTreeMap::new()).await? {
+        FetchOutcome::Pages(fetched) => {
+            for failed in &fetched.failed {
+                eprintln!("Warning: Could not fetch {}: {}", failed.path, failed.message);
+            }
+            Ok(fetched.pages)
+        },
+        FetchOutcome::Unchanged => Err("Pages reported as unchanged without previous validators".into()),
+    }
+}
+
+/// Replays a recording if the directory has one, otherwise reads every .html file in it.
+async fn read_directory(directory: &Path, source_profile: &SourceProfile, profile: &CityProfile, config: &FetchConfig) -> Result<Vec<Vec<u8>>, Error> {
+    if directory.join("index.json").exists() {
+        return fetch_pages(ReplayPageSource::new(directory.to_path_buf())?, source_profile, profile, config).await;
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |extension| extension == "html"))
+        .collect();
+    // Sorting on length first puts page-2.html before page-10.html
+    paths.sort_by_key(|path| (path.as_os_str().len(), path.clone()));
+    let mut pages: Vec<Vec<u8>> = Vec::new();
+    for path in paths {
+        pages.push(fs::read(path)?);
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::scraper::test_resources;
+
+    fn args(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn should_default_to_network_and_json() {
+        let parsed = args(&[]).unwrap().unwrap();
+        assert_eq!(Input::Network{ base_url: None, record: None }, parsed.input);
+        assert_eq!(Format::Json, parsed.format);
+        assert!(!parsed.diagnostics);
+    }
+
+    #[test]
+    fn should_parse_all_options() {
+        let parsed = args(&["--dir", "pages", "--format", "csv", "--diagnostics", "--date", "2020-09-01"]).unwrap().unwrap();
+        assert_eq!(Input::Directory(PathBuf::from("pages")), parsed.input);
+        assert_eq!(Format::Csv, parsed.format);
+        assert!(parsed.diagnostics);
+        assert_eq!(Some(NaiveDate::from_ymd(2020, 9, 1)), parsed.scrape_date);
+    }
+
+    #[test]
+    fn should_reject_conflicting_inputs() {
+        assert!(args(&["--dir", "pages", "--stdin"]).is_err());
+        assert!(args(&["--stdin", "--record", "pages"]).is_err());
+        assert!(args(&["--format"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_read_recorded_directory() {
+        let directory = test_resources();
+        let profile = CityProfile::goteborg();
+        let pages = read_directory(&directory, &profile.sources[0], &profile, &FetchConfig::default()).await.unwrap();
+        assert_eq!(6, pages.len());
+    }
+}
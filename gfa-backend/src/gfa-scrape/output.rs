@@ -0,0 +1,132 @@
+use std::str::FromStr;
+use serde::Serialize;
+use common::pickup_event::PickUpEvent;
+use common::scraper::page_parser::SnippetDiagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            other => Err(format!("Unknown format '{}', expected json, csv or ndjson", other)),
+        }
+    }
+}
+
+/// Something which can be written as a CSV row.
+pub trait CsvRecord {
+    fn csv_header() -> &'static [&'static str];
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+impl CsvRecord for PickUpEvent {
+    fn csv_header() -> &'static [&'static str] {
+        &["service_id", "location_id", "street", "district", "organisation_type", "description", "time_start", "time_end", "date", "status"]
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.service_id.clone(),
+            self.location_id.clone(),
+            self.street.clone(),
+            self.district.clone(),
+            self.organisation_type.clone().unwrap_or_default(),
+            self.description.clone().unwrap_or_default(),
+            self.time_start.to_rfc3339(),
+            self.time_end.to_rfc3339(),
+            self.date.to_string(),
+            self.status.to_string(),
+        ]
+    }
+}
+
+impl CsvRecord for SnippetDiagnostic {
+    fn csv_header() -> &'static [&'static str] {
+        &["page_index", "stage", "severity", "error", "snippet"]
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.page_index.to_string(),
+            format!("{:?}", self.stage),
+            format!("{:?}", self.severity),
+            self.error.trim().to_owned(),
+            self.snippet.clone(),
+        ]
+    }
+}
+
+pub fn render<T: Serialize + CsvRecord>(records: &[T], format: Format) -> Result<String, serde_json::Error> {
+    match format {
+        Format::Json => Ok(format!("{}\n", serde_json::to_string_pretty(records)?)),
+        Format::Ndjson => {
+            let mut output = String::new();
+            for record in records {
+                output.push_str(&serde_json::to_string(record)?);
+                output.push('\n');
+            }
+            Ok(output)
+        },
+        Format::Csv => {
+            let mut output = csv_row(T::csv_header().iter().map(|field| field.to_string()).collect());
+            for record in records {
+                output.push_str(&csv_row(record.csv_fields()));
+            }
+            Ok(output)
+        },
+    }
+}
+
+fn csv_row(fields: Vec<String>) -> String {
+    let fields: Vec<String> = fields.into_iter()
+        .map(|field| match field.contains(&[',', '"', '\n', '\r'][..]) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field,
+        })
+        .collect();
+    format!("{}\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+
+    fn event() -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Doktor Fries torg, Doktor Bondesons Gata".to_owned(), "Centrum".to_owned(), Some("Kommunal".to_owned()), Some("vid \"skolan\"".to_owned()), parse_time("2020-09-23T18:00:00+02:00").unwrap(), parse_time("2020-09-23T18:45:00+02:00").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_parse_format() {
+        assert_eq!(Format::Ndjson, Format::from_str("NDJSON").unwrap());
+        assert!(Format::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn should_quote_csv_fields() {
+        let output = render(&[event()], Format::Csv).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!("service_id,location_id,street,district,organisation_type,description,time_start,time_end,date,status", lines[0]);
+        assert_eq!("farligt-avfall,farligt-avfall:centrum_doktor-fries-torg-doktor-bondesons-gata,\"Doktor Fries torg, Doktor Bondesons Gata\",Centrum,Kommunal,\"vid \"\"skolan\"\"\",2020-09-23T16:00:00+00:00,2020-09-23T16:45:00+00:00,2020-09-23,scheduled", lines[1]);
+    }
+
+    #[test]
+    fn should_write_one_event_per_line_as_ndjson() {
+        let output = render(&[event(), event()], Format::Ndjson).unwrap();
+        assert_eq!(2, output.lines().count());
+        for line in output.lines() {
+            let parsed: PickUpEvent = serde_json::from_str(line).unwrap();
+            assert_eq!("Centrum", parsed.district);
+        }
+    }
+}
@@ -1,66 +1,104 @@
-use std::error::Error;
-use chrono::{DateTime};
-use chrono_tz::Europe::Stockholm;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use common::city_profile::CityProfile;
 use common::pickup_event::PickUpEvent;
 
-pub fn format_email_message(event: &PickUpEvent) -> Option<String> {
+/// Formats the reminder for all of today's visits to one stop, which are given in order of time. `nearby` are today's visits to
+/// the other stops in the same area, in case one of them suits better. `service_name` is what the collection service is called.
+pub fn format_email_message(visits: &[PickUpEvent], nearby: &[&PickUpEvent], service_name: &str, profile: &CityProfile) -> String {
+    let event = &visits[0];
     let email_content = include_str!("notify_email.html");
+    let email_content = email_content.replace("#SENDER#", &profile.sender.name);
+    let email_content = email_content.replace("#SERVICE#", service_name);
+    let email_content = email_content.replace("#CITY#", &profile.name);
     let email_content = email_content.replace("#STREET#", &event.street);
     let description = event.description.as_ref().map_or("".to_owned(), |description| format!("({})", description));
     let email_content = email_content.replace("#DESCRIPTION#", &description);
+    let email_content = email_content.replace("#DATE#", &local_date(event.time_start, profile.time_zone));
+    let times: Vec<String> = visits.iter()
+        .map(|visit| format!("between {} and {}", local_time(visit.time_start, profile.time_zone), local_time(visit.time_end, profile.time_zone)))
+        .collect();
+    let email_content = email_content.replace("#TIMES#", &times.join(" and "));
+    email_content.replace("#NEARBY#", &format_nearby(nearby, profile))
+}
 
-    let date = match rfc3339_string_to_date(&event.time_start) {
-        Ok(date) => date,
-        Err(_e) => return None,
-    };
-    let email_content = email_content.replace("#DATE#", &date);
-
-    let start_time = match rfc3339_string_to_local_time(&event.time_start) {
-        Ok(start_time) => start_time,
-        Err(_e) => return None,
-    };
-    let email_content = email_content.replace("#START#", &start_time);
-
-    let end_time = match rfc3339_string_to_local_time(&event.time_end) {
-        Ok(end_time) => end_time,
-        Err(_e) => return None,
-    };
-    let email_content = email_content.replace("#END#", &end_time);
-
-    Some(email_content)
+fn format_nearby(nearby: &[&PickUpEvent], profile: &CityProfile) -> String {
+    if nearby.is_empty() {
+        return "".to_owned();
+    }
+    let stops: Vec<String> = nearby.iter()
+        .map(|visit| format!("{} between {} and {}", visit.street, local_time(visit.time_start, profile.time_zone), local_time(visit.time_end, profile.time_zone)))
+        .collect();
+    format!("It will also be in {} at {}.<br>", nearby[0].district, stops.join(", "))
 }
 
-fn rfc3339_string_to_date(rfc_string: &str) -> Result<String, Box<dyn Error>> {
-    let date_time = DateTime::parse_from_rfc3339(rfc_string)?
-        .with_timezone(&Stockholm);
-    Ok(date_time.format("%Y-%m-%d").to_string())
+fn local_date(time: DateTime<Utc>, time_zone: Tz) -> String {
+    time.with_timezone(&time_zone).format("%Y-%m-%d").to_string()
 }
 
-fn rfc3339_string_to_local_time(rfc_string: &str) -> Result<String, Box<dyn Error>> {
-    let date_time = DateTime::parse_from_rfc3339(rfc_string)?
-        .with_timezone(&Stockholm);
-    Ok(date_time.format("%H:%M").to_string())
+fn local_time(time: DateTime<Utc>, time_zone: Tz) -> String {
+    time.with_timezone(&time_zone).format("%H:%M").to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use common::pickup_event::parse_time;
+
+    #[test]
+    fn should_convert_to_local_time_in_daylight_saving() {
+        let result = local_time(parse_time("2020-06-06T06:00:00+00:00").unwrap(), Tz::Europe__Stockholm);
+        assert_eq!("08:00".to_owned(), result);
+    }
+
+    #[test]
+    fn should_convert_to_local_time() {
+        let result = local_time(parse_time("2020-11-18T06:00:00+00:00").unwrap(), Tz::Europe__Stockholm);
+        assert_eq!("07:00".to_owned(), result);
+    }
+
+    #[test]
+    fn should_convert_to_local_date() {
+        let result = local_date(parse_time("2020-11-18T06:00:00+00:00").unwrap(), Tz::Europe__Stockholm);
+        assert_eq!("2020-11-18".to_owned(), result);
+    }
+
+    #[test]
+    fn should_name_city_in_email() {
+        let event = PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T18:00:00+01:00").unwrap(), parse_time("2020-11-18T18:45:00+01:00").unwrap()).unwrap();
+        let mut profile = CityProfile::goteborg();
+        profile.name = "Mölndal".to_owned();
+        let content = format_email_message(&[event], &[], "Farligt Avfall-bilen", &profile);
+        assert!(content.contains("at Farligt Avfall-bilen in Mölndal!"));
+        assert!(content.contains("between 18:00 and 18:45"));
+    }
 
     #[test]
-    fn should_convert_rfc3339_string_correctly_daylight_saving() {
-        let result = rfc3339_string_to_local_time("2020-06-06T06:00:00+00:00");
-        assert_eq!("08:00".to_owned(), result.unwrap());
+    fn should_name_service_in_email() {
+        let event = PickUpEvent::new("miljobilen", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T18:00:00+01:00").unwrap(), parse_time("2020-11-18T18:45:00+01:00").unwrap()).unwrap();
+        let content = format_email_message(&[event], &[], "Miljöbilen", &CityProfile::goteborg());
+        assert!(content.contains("at Miljöbilen in Göteborg!"));
     }
 
     #[test]
-    fn should_convert_rfc3339_string_to_time_correctly() {
-        let result = rfc3339_string_to_local_time("2020-11-18T06:00:00+00:00");
-        assert_eq!("07:00".to_owned(), result.unwrap());
+    fn should_list_every_visit_of_the_day() {
+        let visits = vec![
+            PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T10:00:00+01:00").unwrap(), parse_time("2020-11-18T10:45:00+01:00").unwrap()).unwrap(),
+            PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T18:00:00+01:00").unwrap(), parse_time("2020-11-18T18:45:00+01:00").unwrap()).unwrap(),
+        ];
+        let content = format_email_message(&visits, &[], "Farligt Avfall-bilen", &CityProfile::goteborg());
+        assert!(content.contains("on 2020-11-18, between 10:00 and 10:45 and between 18:00 and 18:45."));
+        assert!(!content.contains("It will also be in"));
     }
 
     #[test]
-    fn should_convert_rfc3339_string_to_date() {
-        let result = rfc3339_string_to_date("2020-11-18T06:00:00+00:00");
-        assert_eq!("2020-11-18".to_owned(), result.unwrap());
+    fn should_list_other_stops_in_the_area() {
+        let visit = PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T18:00:00+01:00").unwrap(), parse_time("2020-11-18T18:45:00+01:00").unwrap()).unwrap();
+        let nearby = [
+            PickUpEvent::new("farligt-avfall", "Kungsgatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T17:00:00+01:00").unwrap(), parse_time("2020-11-18T17:45:00+01:00").unwrap()).unwrap(),
+            PickUpEvent::new("farligt-avfall", "Lilla gatan".to_owned(), "Centrum".to_owned(), None, None, parse_time("2020-11-18T19:00:00+01:00").unwrap(), parse_time("2020-11-18T19:45:00+01:00").unwrap()).unwrap(),
+        ];
+        let content = format_email_message(&[visit], &nearby.iter().collect::<Vec<&PickUpEvent>>(), "Farligt Avfall-bilen", &CityProfile::goteborg());
+        assert!(content.contains("It will also be in Centrum at Kungsgatan between 17:00 and 17:45, Lilla gatan between 19:00 and 19:45."));
     }
 }
@@ -1,27 +1,22 @@
-use std::{env, fmt, error, str::FromStr};
+use std::{env, str::FromStr, collections::BTreeMap};
 use lambda::{handler_fn, Context};
 use serde_json::{json, Value};
 use simple_logger::{SimpleLogger};
 use log::{self, error, info, LevelFilter};
 use rusoto_core::Region;
 use chrono::{Utc};
-use common::events_repo::{get_by_date};
-use common::subscriptions_repo::{get_authenticated_subscriptions};
+use common::events_repo::{EventsRepository, DynamoDbEventsRepository};
+use common::pickup_event::{PickUpEvent, EventStatus};
+use common::stops_repo::{StopsRepository, DynamoDbStopsRepository};
+use common::city_profile::CityProfile;
+use common::subscription::Subscription;
+use common::subscriptions_repo::{SubscriptionsRepository, DynamoDbSubscriptionsRepository};
 use common::send_email::{send_email, SendEmailRequest, Recipient, From};
 
 mod email_formatter;
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-#[derive(Debug)]
-struct MalformedEvent;
-impl fmt::Display for MalformedEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Malformed data in event. Could not format notification email.")
-    }
-}
-impl error::Error for MalformedEvent {}
-
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let _log = SimpleLogger::new()
@@ -33,72 +28,167 @@ async fn main() -> Result<(), Error> {
 }
 
 async fn handle_request(_event: Value, _: Context) -> Result<Value, Error> {
-    let event_table = env::var("EVENTS_TABLE").unwrap();
+    let events_table = env::var("EVENTS_TABLE").unwrap();
     let subscriptions_table = env::var("SUBSCRIPTIONS_TABLE").unwrap();
+    let location_index = env::var("LOCATION_INDEX").unwrap();
+    let aliases_table = env::var("ALIASES_TABLE").unwrap();
     let api_key = env::var("SENDGRID_API_KEY").unwrap();
     let email_domain = env::var("EMAIL_DOMAIN").unwrap();
     let unsubscribe_url = env::var("UNSUBSCRIBE_URL").unwrap();
+    let profile = CityProfile::from_env()?;
     let region = env::var("AWS_REGION").unwrap();
     let region = Region::from_str(&region).unwrap(); 
 
-    let todays_date = Utc::today().format("%Y-%m-%d").to_string();
-    info!("Fetching events for: {}", todays_date);
-    let todays_events = get_by_date(event_table, region.clone(), todays_date).await?;
-    info!("About to notify for {} events", todays_events.len());
+    let events = DynamoDbEventsRepository::new(&region, &events_table, &location_index);
+    let stops = DynamoDbStopsRepository::new(&region, &events_table, &location_index, &aliases_table);
+    let subscriptions_repository = DynamoDbSubscriptionsRepository::new(&region, &subscriptions_table);
 
+    let todays_date = profile.local_date(Utc::now());
+    info!("Fetching events for: {}", todays_date);
+    let todays_events = events.get_by_date(todays_date).await?;
+    // A stop can be visited more than once a day, its subscribers get one reminder which lists every visit. The reminder also lists
+    // the other stops in the same area, so the visits are grouped by area first.
+    let mut visits_by_area: BTreeMap<String, BTreeMap<String, Vec<PickUpEvent>>> = BTreeMap::new();
     for event in todays_events {
-        let subscriptions = match get_authenticated_subscriptions(&subscriptions_table, &region, &event.location_id).await {
-            Ok(subscriptions) => subscriptions, 
-            Err(error) => {
-                error!("Failed to get subscriptions for: {}", event);
-                return Err(error);
-            }
-        };
-        if subscriptions.is_empty() {
-            info!("Skipped sending notifications for {}, since there are no subscribers.", event);
+        if event.status == EventStatus::Cancelled {
+            info!("Skipped sending notifications for {}, since the visit is cancelled.", event);
             continue;
         }
-
-        let html_content = match email_formatter::format_email_message(&event) {
-            Some(content) => content,
-            None => {
-                error!("Unable to format email for: {}", event);
-                return Err(Box::new(MalformedEvent))
-            }
-        };
-        let email_request = SendEmailRequest{
-            from: From {
-                name: "Göteborg Farligt Avfall Notifications".to_owned(),
-                email: format!("noreply-farligtavfall@{}", email_domain),
-            },
-            subject: format!("Farligt Avfall-bilen to {}", event.street),
-            recipients: subscriptions.iter()
-                .map(|subscription| Recipient{
-                    email: subscription.email.clone(),
-                    substitutions: [
-                        ("-unsubscribeUrl-".to_owned(), format!("{}?email={}&unsubscribe_token={}",
-                            unsubscribe_url,
-                            subscription.email.clone(),
-                            match subscription.unsubscribe_token.as_ref() {
-                                Some(unsubscribe_token) => unsubscribe_token.clone(),
-                                None => "MISSING-TOKEN".to_owned() // TODO: Decide on what action to take here
-                            }))
-                    ]
-                    .iter()
-                    .cloned()
-                    .collect()
-                })
-                .collect(),
-            html_content,
-        };
-        match send_email(&api_key, email_request).await {
-            Ok(_res) => {
-                info!("Successfully sent notification email for: {}", event);
-            }
-            Err(_e) => {
-                error!("Unable to send notification email for: {}", event);
+        visits_by_area.entry(event.district.clone()).or_default()
+            .entry(event.location_id.clone()).or_default()
+            .push(event);
+    }
+    for (area, visits_by_stop) in &visits_by_area {
+        info!("About to notify for {} stops in {}", visits_by_stop.len(), area);
+        for (location_id, visits) in visits_by_stop {
+            let nearby = nearby_visits(visits_by_stop, location_id);
+            let event = &visits[0];
+            let subscriptions = match subscribers(&stops, &subscriptions_repository, &event.location_id).await {
+                Ok(subscriptions) => subscriptions,
+                Err(error) => {
+                    error!("Failed to get subscriptions for: {}", event);
+                    return Err(error);
+                }
+            };
+            if subscriptions.is_empty() {
+                info!("Skipped sending notifications for {}, since there are no subscribers.", event);
+                continue;
             }
-        };
+
+            // Stops of a service which is no longer in the profile are named by the id of the service
+            let service_name = match profile.source(&event.service_id) {
+                Some(source) => source.display_name.as_str(),
+                None => event.service_id.as_str(),
+            };
+            let html_content = email_formatter::format_email_message(visits, &nearby, service_name, &profile);
+            let email_request = SendEmailRequest{
+                from: From {
+                    name: profile.sender.name.clone(),
+                    email: profile.sender.address(&email_domain),
+                },
+                subject: match visits.iter().any(|visit| visit.status == EventStatus::Moved) {
+                    true => format!("New time: {} to {}", service_name, event.street),
+                    false => format!("{} to {}", service_name, event.street),
+                },
+                recipients: subscriptions.iter()
+                    .map(|subscription| Recipient{
+                        email: subscription.email.clone(),
+                        substitutions: [
+                            ("-unsubscribeUrl-".to_owned(), format!("{}?email={}&unsubscribe_token={}",
+                                unsubscribe_url,
+                                subscription.email.clone(),
+                                match subscription.unsubscribe_token.as_ref() {
+                                    Some(unsubscribe_token) => unsubscribe_token.clone(),
+                                    None => "MISSING-TOKEN".to_owned() // TODO: Decide on what action to take here
+                                }))
+                        ]
+                        .iter()
+                        .cloned()
+                        .collect()
+                    })
+                    .collect(),
+                html_content,
+            };
+            match send_email(&api_key, email_request).await {
+                Ok(_res) => {
+                    info!("Successfully sent notification email for: {}", event);
+                }
+                Err(_e) => {
+                    error!("Unable to send notification email for: {}", event);
+                }
+            };
+        }
     }
     Ok(json!({}))
 }
+
+/// Today's visits to the other stops in an area, in order of time.
+fn nearby_visits<'a>(visits_by_stop: &'a BTreeMap<String, Vec<PickUpEvent>>, location_id: &str) -> Vec<&'a PickUpEvent> {
+    let mut nearby: Vec<&PickUpEvent> = visits_by_stop.iter()
+        .filter(|(other_location_id, _)| *other_location_id != location_id)
+        .flat_map(|(_, visits)| visits)
+        .collect();
+    nearby.sort_by_key(|visit| visit.time_start);
+    nearby
+}
+
+/// Returns the authenticated subscriptions of a location, including those made with any id it has had before.
+async fn subscribers(stops: &dyn StopsRepository, subscriptions: &dyn SubscriptionsRepository, location_id: &str) -> Result<Vec<Subscription>, Error> {
+    let mut subscribers = subscriptions.get_authenticated_subscriptions(location_id).await?;
+    for alias in stops.get_aliases(location_id).await? {
+        subscribers.append(&mut subscriptions.get_authenticated_subscriptions(&alias).await?);
+    }
+    // Someone may have subscribed both before and after the location got a new id
+    subscribers.sort_by(|a, b| a.email.cmp(&b.email));
+    subscribers.dedup_by(|a, b| a.email == b.email);
+    Ok(subscribers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::{LocationAlias, parse_time};
+    use common::stops_repo::InMemoryStopsRepository;
+    use common::subscriptions_repo::InMemorySubscriptionsRepository;
+
+    fn verified(email: &str, location_id: &str) -> Subscription {
+        let mut subscription = Subscription::new(email, location_id);
+        subscription.verify();
+        subscription
+    }
+
+    #[tokio::test]
+    async fn should_find_subscribers_of_previous_location_ids() {
+        let stops = InMemoryStopsRepository::default();
+        stops.store_aliases(&[LocationAlias{
+            alias_id: "centrum_storgatan".to_owned(),
+            location_id: "farligt-avfall:centrum_storgatan".to_owned(),
+        }]).await.unwrap();
+        let subscriptions = InMemorySubscriptionsRepository::with_subscriptions(vec![
+            verified("a@email.com", "farligt-avfall:centrum_storgatan"),
+            verified("a@email.com", "centrum_storgatan"),
+            verified("b@email.com", "centrum_storgatan"),
+            Subscription::new("c@email.com", "farligt-avfall:centrum_storgatan"),
+        ]);
+        let subscribers = subscribers(&stops, &subscriptions, "farligt-avfall:centrum_storgatan").await.unwrap();
+        assert_eq!(vec!["a@email.com", "b@email.com"], subscribers.iter().map(|s| s.email.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn should_list_visits_to_other_stops_in_the_area() {
+        let visit = |street: &str, start: &str, end: &str| PickUpEvent::new("farligt-avfall", street.to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap();
+        let mut visits_by_stop: BTreeMap<String, Vec<PickUpEvent>> = BTreeMap::new();
+        for event in [
+            visit("Storgatan", "2020-11-18T17:00:00+00:00", "2020-11-18T17:45:00+00:00"),
+            visit("Kungsgatan", "2020-11-18T18:00:00+00:00", "2020-11-18T18:45:00+00:00"),
+            visit("Lilla gatan", "2020-11-18T16:00:00+00:00", "2020-11-18T16:45:00+00:00"),
+        ] {
+            visits_by_stop.entry(event.location_id.clone()).or_default().push(event);
+        }
+        let nearby: Vec<&str> = nearby_visits(&visits_by_stop, "farligt-avfall:centrum_storgatan").iter()
+            .map(|visit| visit.street.as_str())
+            .collect();
+        assert_eq!(vec!["Lilla gatan", "Kungsgatan"], nearby);
+    }
+}
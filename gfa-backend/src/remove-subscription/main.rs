@@ -4,7 +4,7 @@ use log::{self, error, LevelFilter};
 use lambda::{handler_fn, Context};
 use aws_lambda_events::event::apigw::{ApiGatewayV2httpRequest, ApiGatewayV2httpResponse};
 use rusoto_core::Region;
-use common::subscriptions_repo::{get_subscription_by_unsubscribe_token, remove_subscription};
+use common::subscriptions_repo::{SubscriptionsRepository, DynamoDbSubscriptionsRepository};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -37,26 +37,31 @@ async fn handle_request(
         }
     };
 
-    let subscription_from_db = match get_subscription_by_unsubscribe_token(&subscriptions_table, &region, unsubscribe_token).await {
+    let subscriptions = DynamoDbSubscriptionsRepository::new(&region, &subscriptions_table);
+    Ok(remove_subscription(&subscriptions, email, unsubscribe_token).await)
+}
+
+async fn remove_subscription(subscriptions: &dyn SubscriptionsRepository, email: &str, unsubscribe_token: &str) -> ApiGatewayV2httpResponse {
+    let subscription_from_db = match subscriptions.get_subscription_by_unsubscribe_token(unsubscribe_token).await {
         Ok(optional_subscription) => match optional_subscription {
             Some(subscription) => subscription,
-            None => return Ok(create_response(404, "Subscription not found".to_owned()))
+            None => return create_response(404, "Subscription not found".to_owned())
         },
         Err(error) => {
             error!("Failed to read from database: {}", error);
-            return Ok(create_response(500, "Internal error when removing subscription".to_owned()))
+            return create_response(500, "Internal error when removing subscription".to_owned())
         }
     };
 
-    if subscription_from_db.email != *email {
-        return Ok(create_response(400, "Bad unsubscribe token".to_owned()));
+    if subscription_from_db.email != email {
+        return create_response(400, "Bad unsubscribe token".to_owned());
     }
 
-    match remove_subscription(&subscriptions_table, &region, &subscription_from_db).await {
-        Ok(()) => Ok(create_response(200, "Successfully removed subscription".to_owned())),
+    match subscriptions.remove_subscription(&subscription_from_db).await {
+        Ok(()) => create_response(200, "Successfully removed subscription".to_owned()),
         Err(error) => {
             error!("Failed to write to database: {}", error);
-            Ok(create_response(500, "Internal error when removing subscription".to_owned()))
+            create_response(500, "Internal error when removing subscription".to_owned())
         }
     }
 }
@@ -71,3 +76,28 @@ fn create_response(status_code: i64, body: String) -> ApiGatewayV2httpResponse {
         cookies: Vec::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::subscription::Subscription;
+    use common::subscriptions_repo::InMemorySubscriptionsRepository;
+
+    #[tokio::test]
+    async fn should_remove_subscription() {
+        let mut subscription = Subscription::new("email@email.com", "farligt-avfall:centrum_storgatan");
+        subscription.verify();
+        let unsubscribe_token = subscription.unsubscribe_token.clone().unwrap();
+        let subscriptions = InMemorySubscriptionsRepository::with_subscriptions(vec![subscription]);
+        let response = remove_subscription(&subscriptions, "email@email.com", &unsubscribe_token).await;
+        assert_eq!(200, response.status_code);
+        assert_eq!(None, subscriptions.get_subscription("email@email.com", "farligt-avfall:centrum_storgatan").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_not_find_unknown_token() {
+        let subscriptions = InMemorySubscriptionsRepository::default();
+        let response = remove_subscription(&subscriptions, "email@email.com", "unknown").await;
+        assert_eq!(404, response.status_code);
+    }
+}
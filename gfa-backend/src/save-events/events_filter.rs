@@ -1,12 +1,15 @@
-use chrono::{Date, DateTime, Utc, Duration};
+use chrono::{Date, Utc, Duration};
 use common::pickup_event::PickUpEvent;
 
+/// Drops events which are not compared with stored events: visits which have already passed, since stored events are only read
+/// from today, and visits too far into the future.
 pub fn filter(events: Vec<PickUpEvent>, today: Date<Utc>) -> Vec<PickUpEvent> {
     events.into_iter()
         .filter(|event| {
-            let event_date = DateTime::parse_from_rfc3339(&event.time_start).unwrap()
-                .with_timezone(&Utc)
-                .date();
+            if event.date < today.naive_utc() {
+                return false;
+            }
+            let event_date = event.time_start.date();
             let too_far_into_future = today + Duration::weeks(24);
             if event_date > too_far_into_future {
                 return false;
@@ -20,6 +23,7 @@ pub fn filter(events: Vec<PickUpEvent>, today: Date<Utc>) -> Vec<PickUpEvent> {
 mod tests {
     use super::*;
     use chrono::{DateTime};
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
 
     #[test]
     fn shoult_filter_items_too_far_into_future() {
@@ -30,11 +34,25 @@ mod tests {
             .with_timezone(&Utc)
             .date();
         let events: Vec<PickUpEvent> = vec![
-            PickUpEvent::new("some-street".to_owned(), "some-district".to_owned(), None, "2021-08-14T16:00:00+02:00".to_owned(), "2021-08-14T17:00:00+02:00".to_owned()).unwrap(),
-            PickUpEvent::new("some-other-street".to_owned(), "some-other-district".to_owned(), None, "2021-05-15T16:00:00+02:00".to_owned(), "2020-05-15T17:00:00+02:00".to_owned()).unwrap()
+            PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "some-street".to_owned(), "some-district".to_owned(), None, None, parse_time("2021-08-14T16:00:00+02:00").unwrap(), parse_time("2021-08-14T17:00:00+02:00").unwrap()).unwrap(),
+            PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "some-other-street".to_owned(), "some-other-district".to_owned(), None, None, parse_time("2021-05-15T16:00:00+02:00").unwrap(), parse_time("2021-05-15T17:00:00+02:00").unwrap()).unwrap()
         ];
         let result = filter(events, pseudo_today);
         assert_eq!(1, result.len());
         assert_eq!("some-other-street".to_owned(), result[0].street);
     }
+
+    #[test]
+    fn should_filter_items_in_the_past() {
+        let today = DateTime::parse_from_rfc3339("2021-03-10T00:00:00+00:00").unwrap()
+            .with_timezone(&Utc)
+            .date();
+        let events: Vec<PickUpEvent> = vec![
+            PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "some-street".to_owned(), "some-district".to_owned(), None, None, parse_time("2021-03-09T16:00:00+01:00").unwrap(), parse_time("2021-03-09T17:00:00+01:00").unwrap()).unwrap(),
+            PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "some-other-street".to_owned(), "some-other-district".to_owned(), None, None, parse_time("2021-03-10T16:00:00+01:00").unwrap(), parse_time("2021-03-10T17:00:00+01:00").unwrap()).unwrap()
+        ];
+        let result = filter(events, today);
+        assert_eq!(1, result.len());
+        assert_eq!("some-other-street".to_owned(), result[0].street);
+    }
 }
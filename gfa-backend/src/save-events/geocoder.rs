@@ -0,0 +1,291 @@
+use std::{env, fmt, error, fs, sync::Mutex, collections::{HashMap, BTreeMap}};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::time::{self, Duration, Instant};
+use log::{self, info, warn};
+use common::coordinate::Coordinate;
+use common::pickup_event::{PickUpEvent, canonical_segment};
+
+/// The usage policy of the public Nominatim instance allows at most one request per second.
+const NOMINATIM_MIN_INTERVAL: Duration = Duration::from_secs(1);
+const USER_AGENT: &str = "gbg-farligt-avfall";
+
+#[derive(fmt::Debug)]
+pub struct GeocoderError {
+    pub message: String,
+}
+impl fmt::Display for GeocoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for GeocoderError {}
+
+/// Resolves where a stop is from its street and district.
+#[async_trait]
+pub trait Geocoder {
+    /// Returns `Ok(None)` if the geocoder does not know the place.
+    async fn geocode(&self, street: &str, district: &str) -> Result<Option<Coordinate>, GeocoderError>;
+}
+
+/// Creates the geocoder configured for this deployment. GAZETTEER_FILE gives an offline gazetteer, and GEOCODER_URL a
+/// Nominatim-compatible search API. Stops are not geocoded if neither is set.
+pub fn from_env(city: &str) -> Result<Option<Box<dyn Geocoder + Send + Sync>>, GeocoderError> {
+    if let Ok(path) = env::var("GAZETTEER_FILE") {
+        return Ok(Some(Box::new(GazetteerGeocoder::from_file(&path)?)));
+    }
+    if let Ok(base_url) = env::var("GEOCODER_URL") {
+        return Ok(Some(Box::new(NominatimGeocoder::new(&base_url, city, NOMINATIM_MIN_INTERVAL)?)));
+    }
+    Ok(None)
+}
+
+/// Searches a Nominatim-compatible API, e.g. https://nominatim.openstreetmap.org.
+pub struct NominatimGeocoder {
+    client: reqwest::Client,
+    base_url: String,
+    city: String,
+    min_interval: Duration,
+    last_request: Mutex<Option<Instant>>,
+}
+
+#[derive(Deserialize)]
+struct NominatimPlace {
+    lat: String,
+    lon: String,
+}
+
+impl NominatimGeocoder {
+    pub fn new(base_url: &str, city: &str, min_interval: Duration) -> Result<Self, GeocoderError> {
+        let client = match reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .build() {
+            Ok(client) => client,
+            Err(e) => return Err(GeocoderError{
+                message: format!("Could not create HTTP client: {}", e)
+            })
+        };
+        Ok(NominatimGeocoder{
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            city: city.to_owned(),
+            min_interval,
+            last_request: Mutex::new(None),
+        })
+    }
+
+    async fn wait_for_turn(&self) {
+        let wait = {
+            let mut last_request = self.last_request.lock().unwrap();
+            let now = Instant::now();
+            let next_allowed = last_request.map_or(now, |last_request| last_request + self.min_interval);
+            let wait = if next_allowed > now { next_allowed - now } else { Duration::from_secs(0) };
+            *last_request = Some(now + wait);
+            wait
+        };
+        time::delay_for(wait).await;
+    }
+}
+
+#[async_trait]
+impl Geocoder for NominatimGeocoder {
+    async fn geocode(&self, street: &str, district: &str) -> Result<Option<Coordinate>, GeocoderError> {
+        self.wait_for_turn().await;
+        let query = format!("{}, {}, {}", street, district, self.city);
+        let response = match self.client.get(&format!("{}/search", self.base_url))
+            .query(&[("q", query.as_str()), ("format", "json"), ("limit", "1")])
+            .send()
+            .await {
+            Ok(response) => response,
+            Err(e) => return Err(GeocoderError{
+                message: format!("Failed to search for {}: {}", query, e)
+            })
+        };
+        if !response.status().is_success() {
+            return Err(GeocoderError{
+                message: format!("Search for {} failed with status {}", query, response.status())
+            });
+        }
+        let places: Vec<NominatimPlace> = match response.json().await {
+            Ok(places) => places,
+            Err(e) => return Err(GeocoderError{
+                message: format!("Malformed search result for {}: {}", query, e)
+            })
+        };
+        let place = match places.first() {
+            Some(place) => place,
+            None => return Ok(None),
+        };
+        match (place.lat.parse::<f64>(), place.lon.parse::<f64>()) {
+            (Ok(latitude), Ok(longitude)) => Ok(Some(Coordinate::new(latitude, longitude))),
+            _ => Err(GeocoderError{
+                message: format!("Malformed coordinate for {}: {}, {}", query, place.lat, place.lon)
+            })
+        }
+    }
+}
+
+/// Looks stops up in a JSON file of known places, for deployments without access to a geocoding API.
+/// Names are compared the same way as in location ids, so spelling differences in case or punctuation do not matter.
+pub struct GazetteerGeocoder {
+    places: HashMap<(String, String), Coordinate>,
+}
+
+#[derive(Deserialize)]
+struct GazetteerEntry {
+    district: String,
+    street: String,
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GazetteerGeocoder {
+    pub fn from_file(path: &str) -> Result<Self, GeocoderError> {
+        match fs::read_to_string(path) {
+            Ok(json) => GazetteerGeocoder::from_json(&json),
+            Err(e) => Err(GeocoderError{
+                message: format!("Failed to read gazetteer {}: {}", path, e)
+            })
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, GeocoderError> {
+        let entries: Vec<GazetteerEntry> = match serde_json::from_str(json) {
+            Ok(entries) => entries,
+            Err(e) => return Err(GeocoderError{
+                message: format!("Malformed gazetteer: {}", e)
+            })
+        };
+        Ok(GazetteerGeocoder{
+            places: entries.into_iter()
+                .map(|entry| (
+                    (canonical_segment(&entry.district), canonical_segment(&entry.street)),
+                    Coordinate::new(entry.latitude, entry.longitude)
+                ))
+                .collect(),
+        })
+    }
+}
+
+#[async_trait]
+impl Geocoder for GazetteerGeocoder {
+    async fn geocode(&self, street: &str, district: &str) -> Result<Option<Coordinate>, GeocoderError> {
+        Ok(self.places.get(&(canonical_segment(district), canonical_segment(street))).copied())
+    }
+}
+
+/// Gives every event the coordinate of its stop. Stops which already have a coordinate in `known` are not looked up again,
+/// and at most `max_lookups` stops are geocoded, so that a run stays within its time limit. A stop which cannot be geocoded
+/// is only logged, the events are stored without a coordinate and it is tried again next run.
+pub async fn geocode_events(geocoder: &(dyn Geocoder + Send + Sync), events: Vec<PickUpEvent>, known: &HashMap<String, Coordinate>, max_lookups: usize) -> Vec<PickUpEvent> {
+    let mut coordinates: BTreeMap<String, Option<Coordinate>> = BTreeMap::new();
+    let mut lookups = 0_usize;
+    for event in &events {
+        if coordinates.contains_key(&event.location_id) {
+            continue;
+        }
+        if let Some(coordinate) = known.get(&event.location_id) {
+            coordinates.insert(event.location_id.clone(), Some(*coordinate));
+            continue;
+        }
+        if lookups == max_lookups {
+            continue;
+        }
+        lookups += 1;
+        let coordinate = match geocoder.geocode(&event.street, &event.district).await {
+            Ok(Some(coordinate)) => Some(coordinate),
+            Ok(None) => {
+                warn!("Found no coordinate for {}", event.location_id);
+                None
+            },
+            Err(e) => {
+                warn!("Failed to geocode {}: {}", event.location_id, e);
+                None
+            }
+        };
+        coordinates.insert(event.location_id.clone(), coordinate);
+    }
+    info!("Geocoded {} stops", lookups);
+    events.into_iter()
+        .map(|event| {
+            let coordinate = coordinates.get(&event.location_id).copied().flatten();
+            event.with_coordinate(coordinate)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+
+    /// Answers a single HTTP request with the given body, like a Nominatim instance would.
+    async fn serve_once(body: &'static str) -> (SocketAddr, tokio::task::JoinHandle<String>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let length = socket.read(&mut request).await.unwrap();
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..length]).to_string()
+        });
+        (address, server)
+    }
+
+    fn event(street: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, street.to_owned(), "Centrum".to_owned(), None, None, parse_time("2021-03-01T18:00:00+01:00").unwrap(), parse_time("2021-03-01T18:45:00+01:00").unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_geocode_with_nominatim() {
+        let (address, server) = serve_once(r#"[{"place_id": 1, "lat": "57.7072", "lon": "11.9668", "display_name": "Storgatan, Centrum, Göteborg"}]"#).await;
+        let geocoder = NominatimGeocoder::new(&format!("http://{}/", address), "Göteborg", Duration::from_secs(0)).unwrap();
+        let coordinate = geocoder.geocode("Storgatan", "Centrum").await.unwrap();
+        let request = server.await.unwrap();
+        assert_eq!(Some(Coordinate::new(57.7072, 11.9668)), coordinate);
+        assert!(request.starts_with("GET /search?q=Storgatan%2C+Centrum%2C+G%C3%B6teborg&format=json&limit=1 "));
+        assert!(request.to_lowercase().contains("user-agent: gbg-farligt-avfall"));
+    }
+
+    #[tokio::test]
+    async fn should_find_nothing_with_nominatim() {
+        let (address, _server) = serve_once("[]").await;
+        let geocoder = NominatimGeocoder::new(&format!("http://{}", address), "Göteborg", Duration::from_secs(0)).unwrap();
+        assert_eq!(None, geocoder.geocode("Storgatan", "Centrum").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_geocode_with_gazetteer() {
+        let geocoder = GazetteerGeocoder::from_json(r#"[{"district": "Centrum", "street": "Doktor Fries torg", "latitude": 57.7072, "longitude": 11.9668}]"#).unwrap();
+        assert_eq!(Some(Coordinate::new(57.7072, 11.9668)), geocoder.geocode("doktor fries Torg ", "CENTRUM").await.unwrap());
+        assert_eq!(None, geocoder.geocode("Storgatan", "Centrum").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_only_geocode_unknown_stops() {
+        let geocoder = GazetteerGeocoder::from_json(r#"[
+            {"district": "Centrum", "street": "Storgatan", "latitude": 1.0, "longitude": 1.0},
+            {"district": "Centrum", "street": "Lillgatan", "latitude": 2.0, "longitude": 2.0}
+        ]"#).unwrap();
+        let known: HashMap<String, Coordinate> = [(event("Storgatan").location_id, Coordinate::new(3.0, 3.0))].iter().cloned().collect();
+        let events = geocode_events(&geocoder, vec![event("Storgatan"), event("Lillgatan"), event("Lillgatan")], &known, 1).await;
+        assert_eq!(Some(Coordinate::new(3.0, 3.0)), events[0].coordinate);
+        assert_eq!(Some(Coordinate::new(2.0, 2.0)), events[1].coordinate);
+        assert_eq!(Some(Coordinate::new(2.0, 2.0)), events[2].coordinate);
+    }
+
+    #[tokio::test]
+    async fn should_stop_geocoding_after_max_lookups() {
+        let geocoder = GazetteerGeocoder::from_json(r#"[
+            {"district": "Centrum", "street": "Storgatan", "latitude": 1.0, "longitude": 1.0},
+            {"district": "Centrum", "street": "Lillgatan", "latitude": 2.0, "longitude": 2.0}
+        ]"#).unwrap();
+        let events = geocode_events(&geocoder, vec![event("Storgatan"), event("Lillgatan")], &HashMap::new(), 1).await;
+        assert_eq!(Some(Coordinate::new(1.0, 1.0)), events[0].coordinate);
+        assert_eq!(None, events[1].coordinate);
+    }
+}
@@ -1,15 +1,35 @@
-use std::{env, str::FromStr};
+use std::{env, str::FromStr, collections::{BTreeSet, HashMap}};
 use lambda::{handler_fn, Context};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use simple_logger::{SimpleLogger};
 use log::{self, info, error, LevelFilter};
 use rusoto_core::Region;
-use chrono::{Utc};
-use common::{pickup_event::PickUpEvent, events_repo};
+use chrono::{Date, Utc};
+use common::{pickup_event::{PickUpEvent, EventKey, LocationAlias}, coordinate::Coordinate, city_profile::CityProfile};
+use common::events_repo::{EventsRepository, DynamoDbEventsRepository};
+use common::stops_repo::{StopsRepository, DynamoDbStopsRepository};
+use geocoder::Geocoder;
+use reconcile::{RemovalLimit, Removals, DEFAULT_REMOVAL_LIMIT};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 mod events_filter;
+mod geocoder;
+mod reconcile;
+mod renamed_stops;
+mod schedule_diff;
+
+/// The output of the scraper. It is incomplete when some schedule pages could not be fetched.
+#[derive(Deserialize)]
+struct Schedule {
+    events: Vec<PickUpEvent>,
+    #[serde(default)]
+    incomplete: bool,
+}
+
+/// Nominatim allows one request per second, so this keeps a run well within the time limit of the function.
+const DEFAULT_MAX_GEOCODER_LOOKUPS: usize = 150;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -23,24 +43,314 @@ async fn main() -> Result<(), Error> {
 
 async fn handle_request(event: Value, _: Context) -> Result<Value, Error> {
     let events_table = env::var("EVENTS_TABLE").unwrap();
+    let location_index = env::var("LOCATION_INDEX").unwrap();
+    let aliases_table = env::var("ALIASES_TABLE").unwrap();
+    let profile = CityProfile::from_env()?;
+    let max_geocoder_lookups = match env::var("MAX_GEOCODER_LOOKUPS") {
+        Ok(max_lookups) => max_lookups.parse::<usize>()?,
+        Err(_) => DEFAULT_MAX_GEOCODER_LOOKUPS,
+    };
+    let removal_limit = match env::var("MAX_REMOVED_SHARE") {
+        Ok(max_share) => RemovalLimit{
+            max_share: max_share.parse::<f64>()?,
+            ..DEFAULT_REMOVAL_LIMIT
+        },
+        Err(_) => DEFAULT_REMOVAL_LIMIT,
+    };
     let region = env::var("AWS_REGION").unwrap();
     let region = Region::from_str(&region).unwrap(); 
-    let pickup_events: Vec<PickUpEvent> = serde_json::from_value(event)?;
-    let pickup_events = events_filter::filter(pickup_events, Utc::today());
+    let schedule: Schedule = serde_json::from_value(event)?;
+    let events = DynamoDbEventsRepository::new(&region, &events_table, &location_index);
+    let stops = DynamoDbStopsRepository::new(&region, &events_table, &location_index, &aliases_table);
+    let geocoder = geocoder::from_env(&profile.name)?;
+    let changeset = save_events(&events, &stops, geocoder.as_deref(), max_geocoder_lookups, &removal_limit, schedule, Utc::today()).await?;
+    Ok(json!(changeset))
+}
+
+async fn save_events(events: &dyn EventsRepository, stops: &dyn StopsRepository, geocoder: Option<&(dyn Geocoder + Send + Sync)>,
+    max_geocoder_lookups: usize, removal_limit: &RemovalLimit, schedule: Schedule, today: Date<Utc>) -> Result<schedule_diff::Changeset, Error> {
+    // Stored events are only read from today, so the scraped events are limited to the same dates
+    let pickup_events = events_filter::filter(schedule.events, today);
+    if pickup_events.is_empty() {
+        // Comparing with an empty schedule would report every stored event as removed
+        info!("No upcoming events received, leaving stored events as they are");
+        return Ok(schedule_diff::Changeset::default());
+    }
     let total_events = pickup_events.len();
-    // Filter events
-    // Skip in the past, long time in the future, etc
 
-    let _result = match events_repo::store(events_table, region, pickup_events).await {
-        Ok(unprocessed_count) => {
-            let processed_items = total_events - unprocessed_count;
-            info!("Wrote {} / {} items", processed_items, total_events);
+    // A scrape only replaces the schedules of its own services, so stored events of other services are left out of the comparison
+    let services: BTreeSet<String> = pickup_events.iter()
+        .map(|event| event.service_id.clone())
+        .collect();
+    let stored_events: Vec<PickUpEvent> = match events.get_from_date(today.naive_utc()).await {
+        Ok(events) => events.into_iter()
+            .filter(|event| services.contains(&event.service_id))
+            .collect(),
+        Err(e) => {
+            error!("Error when reading stored events:\n {}", e);
+            return Err(e);
+        }
+    };
+    let changeset = schedule_diff::diff(&stored_events, &pickup_events);
+    if changeset.is_empty() {
+        info!("No changes compared with {} stored events", stored_events.len());
+    } else {
+        info!("Compared with {} stored events: {} added, {} removed, {} with changed time, {} with changed description, {} with changed status",
+            stored_events.len(), changeset.added.len(), changeset.removed.len(), changeset.time_changed.len(), changeset.description_changed.len(),
+            changeset.status_changed.len());
+    }
+    // Checked before anything is written, but only acted on after the scraped events are stored, since they are not affected
+    let removals = match schedule.incomplete {
+        true => {
+            info!("Some schedule pages could not be fetched, keeping stored events which are not in the scrape");
+            Ok(Removals::default())
+        },
+        false => reconcile::removals(&changeset.removed, &stored_events, &pickup_events, removal_limit),
+    };
+    let renamed = renamed_stops::renamed_stops(&stored_events, &pickup_events, today.naive_utc());
+
+    let pickup_events = match geocoder {
+        Some(geocoder) => {
+            let known: HashMap<String, Coordinate> = stored_events.iter()
+                .filter_map(|event| event.coordinate.map(|coordinate| (event.location_id.clone(), coordinate)))
+                .collect();
+            geocoder::geocode_events(geocoder, pickup_events, &known, max_geocoder_lookups).await
+        },
+        None => {
+            info!("No geocoder configured, storing events without coordinates");
+            pickup_events
+        }
+    };
+
+    let mut aliases: BTreeSet<LocationAlias> = pickup_events.iter()
+        .flat_map(|event| event.aliases())
+        .collect();
+    for rename in renamed {
+        info!("{} has the same visits as {}, which is no longer scheduled", rename.location_id, rename.alias_id);
+        // Only aliases of the current id are followed, so the aliases of the previous id are moved over
+        let previous_aliases = match stops.get_aliases(&rename.alias_id).await {
+            Ok(previous_aliases) => previous_aliases,
+            Err(e) => {
+                error!("Error when reading aliases of {}:\n {}", rename.alias_id, e);
+                return Err(e);
+            }
+        };
+        aliases.extend(previous_aliases.into_iter().map(|alias_id| LocationAlias{
+            alias_id,
+            location_id: rename.location_id.clone(),
+        }));
+        aliases.insert(rename);
+    }
+    let aliases: Vec<LocationAlias> = aliases.into_iter().collect();
+    match stops.store_aliases(&aliases).await {
+        Ok(()) => {
+            info!("Wrote {} location aliases", aliases.len());
         },
         Err(e) => {
-            error!("Error when writing events:\n {}", e);
+            error!("Error when writing location aliases:\n {}", e);
             return Err(e);
         }
     };
 
-    Ok(json!({}))
+    match events.store(pickup_events).await {
+        Ok(outcome) if outcome.failed.is_empty() => {
+            info!("Wrote {} / {} items, {} writes were retried", outcome.written, total_events, outcome.retried);
+        },
+        Ok(outcome) => {
+            error!("Wrote {} / {} items, gave up on: {:?}", outcome.written, total_events, outcome.failed);
+            return Err(format!("Could not write {} events", outcome.failed.len()).into());
+        },
+        Err(e) => {
+            error!("Error when writing events:\n {}", e);
+            return Err(e.into());
+        }
+    };
+
+    // Events are keyed on their start time, so a visit which got a new start time was written as a new item. When only the end
+    // time changed, the previous key is the one which was just written.
+    let stale_keys: Vec<EventKey> = changeset.time_changed.iter()
+        .filter(|time_change| time_change.previous_start != time_change.time_start)
+        .map(|time_change| time_change.previous_key())
+        .collect();
+    match events.delete(&stale_keys).await {
+        Ok(outcome) => {
+            info!("Deleted {} / {} events which got a new time", outcome.written, stale_keys.len());
+        },
+        Err(e) => {
+            error!("Error when deleting events which got a new time:\n {}", e);
+            return Err(e.into());
+        }
+    };
+
+    // The schedule replaces what is stored, so visits which the city dropped are deleted instead of being notified about
+    let removals = match removals {
+        Ok(removals) => removals,
+        Err(e) => {
+            error!("Kept events which are no longer in the schedule:\n {}", e);
+            return Err(Box::new(e));
+        }
+    };
+    match events.delete(&removals.keys).await {
+        Ok(outcome) => {
+            info!("Deleted {} / {} events which are no longer in the schedule, kept {} after the last scraped date",
+                outcome.written, removals.keys.len(), removals.beyond_covered_dates);
+        },
+        Err(e) => {
+            error!("Error when deleting events which are no longer in the schedule:\n {}", e);
+            return Err(e.into());
+        }
+    };
+
+    Ok(changeset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+    use common::events_repo::InMemoryEventsRepository;
+    use common::stops_repo::InMemoryStopsRepository;
+
+    fn event(street: &str, start: &str, end: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, street.to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap()
+    }
+
+    fn complete(events: Vec<PickUpEvent>) -> Schedule {
+        Schedule{
+            events,
+            incomplete: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_replace_visit_which_got_a_new_time() {
+        let events = InMemoryEventsRepository::with_events(vec![
+            event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00"),
+        ]);
+        let stops = InMemoryStopsRepository::default();
+        let scraped = vec![
+            event("Storgatan", "2020-09-23T17:00:00+00:00", "2020-09-23T17:45:00+00:00"),
+            event("Kungsgatan", "2020-09-24T16:00:00+00:00", "2020-09-24T16:45:00+00:00"),
+        ];
+        let changeset = save_events(&events, &stops, None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped.clone()), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert_eq!(1, changeset.time_changed.len());
+        assert_eq!(1, changeset.added.len());
+        let mut expected = scraped;
+        expected.sort();
+        assert_eq!(expected, events.all());
+    }
+
+    #[tokio::test]
+    async fn should_keep_visit_which_got_a_new_end_time() {
+        let events = InMemoryEventsRepository::with_events(vec![
+            event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00"),
+        ]);
+        let scraped = vec![
+            event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T17:00:00+00:00"),
+        ];
+        let changeset = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped.clone()), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert_eq!(1, changeset.time_changed.len());
+        assert_eq!(scraped, events.all());
+    }
+
+    #[tokio::test]
+    async fn should_not_report_visits_which_have_passed_as_added() {
+        let passed = event("Storgatan", "2020-09-18T16:00:00+00:00", "2020-09-18T16:45:00+00:00");
+        let upcoming = event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00");
+        let events = InMemoryEventsRepository::with_events(vec![passed.clone(), upcoming.clone()]);
+        let scraped = vec![passed, upcoming];
+        let changeset = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert!(changeset.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_keep_stored_events_when_nothing_was_scraped() {
+        let stored = vec![event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00")];
+        let events = InMemoryEventsRepository::with_events(stored.clone());
+        let changeset = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, complete(vec![]), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert!(changeset.is_empty());
+        assert_eq!(stored, events.all());
+    }
+
+    #[tokio::test]
+    async fn should_delete_visits_which_were_dropped_from_the_schedule() {
+        let dropped = event("Kungsgatan", "2020-09-24T16:00:00+00:00", "2020-09-24T16:45:00+00:00");
+        let after_scrape = event("Kungsgatan", "2020-12-24T16:00:00+00:00", "2020-12-24T16:45:00+00:00");
+        let kept = event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00");
+        let events = InMemoryEventsRepository::with_events(vec![dropped.clone(), after_scrape.clone(), kept.clone()]);
+        let scraped = vec![
+            kept.clone(),
+            event("Storgatan", "2020-10-23T16:00:00+00:00", "2020-10-23T16:45:00+00:00"),
+        ];
+        let changeset = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped.clone()), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert_eq!(vec![dropped, after_scrape.clone()], changeset.removed);
+        let mut expected = scraped;
+        expected.push(after_scrape);
+        expected.sort();
+        assert_eq!(expected, events.all());
+    }
+
+    #[tokio::test]
+    async fn should_keep_subscriptions_of_renamed_street() {
+        let renamed = PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Doktor Fries torg".to_owned(), "Johanneberg".to_owned(), None, None,
+            parse_time("2020-09-23T16:00:00+00:00").unwrap(), parse_time("2020-09-23T16:45:00+00:00").unwrap()).unwrap();
+        let events = InMemoryEventsRepository::with_events(vec![renamed.clone()]);
+        let stops = InMemoryStopsRepository::default();
+        stops.store_aliases(&[LocationAlias{
+            alias_id: "johanneberg_doktorfriestorg".to_owned(),
+            location_id: renamed.location_id.clone(),
+        }]).await.unwrap();
+        let scraped = vec![PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, "Doktor Friis torg".to_owned(), "Johanneberg".to_owned(), None, None,
+            parse_time("2020-09-23T16:00:00+00:00").unwrap(), parse_time("2020-09-23T16:45:00+00:00").unwrap()).unwrap()];
+        save_events(&events, &stops, None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped), Utc.ymd(2020, 9, 20)).await.unwrap();
+        let aliases = stops.get_aliases("farligt-avfall:johanneberg_doktor-friis-torg").await.unwrap();
+        assert!(aliases.contains(&"farligt-avfall:johanneberg_doktor-fries-torg".to_owned()));
+        assert!(aliases.contains(&"johanneberg_doktorfriestorg".to_owned()));
+        assert_eq!("farligt-avfall:johanneberg_doktor-friis-torg",
+            stops.resolve_location_id("farligt-avfall:johanneberg_doktor-fries-torg").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_leave_events_of_other_services() {
+        let other_service = PickUpEvent::new("batteriinsamling", "Storgatan".to_owned(), "Centrum".to_owned(), None, None,
+            parse_time("2020-09-23T16:00:00+00:00").unwrap(), parse_time("2020-09-23T16:45:00+00:00").unwrap()).unwrap();
+        let scraped = vec![event("Storgatan", "2020-09-23T16:00:00+00:00", "2020-09-23T16:45:00+00:00")];
+        let events = InMemoryEventsRepository::with_events(vec![other_service.clone()]);
+        let changeset = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, complete(scraped.clone()), Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert!(changeset.removed.is_empty());
+        assert_eq!(1, changeset.added.len());
+        let mut expected = vec![other_service, scraped[0].clone()];
+        expected.sort();
+        assert_eq!(expected, events.all());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_to_delete_most_of_the_schedule() {
+        let stored: Vec<PickUpEvent> = (1..=9)
+            .map(|day| event(&format!("Gata {}", day), &format!("2020-10-0{}T16:00:00+00:00", day), &format!("2020-10-0{}T16:45:00+00:00", day)))
+            .collect();
+        let events = InMemoryEventsRepository::with_events(stored.clone());
+        let limit = RemovalLimit{
+            max_share: 0.2,
+            always_allowed: 1,
+        };
+        let result = save_events(&events, &InMemoryStopsRepository::default(), None, 0, &limit, complete(vec![stored[8].clone()]), Utc.ymd(2020, 9, 20)).await;
+        assert!(result.is_err());
+        assert_eq!(9, events.all().len());
+    }
+
+    #[tokio::test]
+    async fn should_keep_events_missing_from_incomplete_schedule() {
+        let missing = event("Kungsgatan", "2020-09-24T16:00:00+00:00", "2020-09-24T16:45:00+00:00");
+        let scraped = event("Storgatan", "2020-09-25T16:00:00+00:00", "2020-09-25T16:45:00+00:00");
+        let events = InMemoryEventsRepository::with_events(vec![missing.clone()]);
+        let schedule = Schedule{
+            events: vec![scraped.clone()],
+            incomplete: true,
+        };
+        save_events(&events, &InMemoryStopsRepository::default(), None, 0, &DEFAULT_REMOVAL_LIMIT, schedule, Utc.ymd(2020, 9, 20)).await.unwrap();
+        assert_eq!(vec![missing, scraped], events.all());
+    }
 }
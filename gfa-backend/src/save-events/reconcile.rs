@@ -0,0 +1,112 @@
+use std::{fmt, error};
+use chrono::NaiveDate;
+use common::pickup_event::{PickUpEvent, EventKey};
+
+#[derive(fmt::Debug)]
+pub struct ReconcileError {
+    pub message: String,
+}
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for ReconcileError {}
+
+/// How many stored events a single run may remove. A scrape which lost part of the schedule would otherwise delete
+/// every visit it missed.
+#[derive(fmt::Debug, Clone, Copy)]
+pub struct RemovalLimit {
+    /// The share of the stored events in the covered dates which may be removed.
+    pub max_share: f64,
+    /// This many removals are always allowed, so that a few cancelled visits in a small schedule are not refused.
+    pub always_allowed: usize,
+}
+
+pub const DEFAULT_REMOVAL_LIMIT: RemovalLimit = RemovalLimit{
+    max_share: 0.2,
+    always_allowed: 5,
+};
+
+/// The stored events which are no longer in the schedule, and can be deleted.
+#[derive(fmt::Debug, Default, PartialEq)]
+pub struct Removals {
+    pub keys: Vec<EventKey>,
+    /// Removed events after the last scraped date, which are kept since the scrape says nothing about them.
+    pub beyond_covered_dates: usize,
+}
+
+/// Picks the removed events which are within the dates the scrape covers, i.e. up to its last visit, and checks them
+/// against the limit. `stored` are the stored events of the scraped services from today on, which the changeset was made from.
+pub fn removals(removed: &[PickUpEvent], stored: &[PickUpEvent], scraped: &[PickUpEvent], limit: &RemovalLimit) -> Result<Removals, ReconcileError> {
+    let last_scraped_date: NaiveDate = match scraped.iter().map(|event| event.date).max() {
+        Some(date) => date,
+        None => return Ok(Removals::default()),
+    };
+    let (covered, beyond): (Vec<&PickUpEvent>, Vec<&PickUpEvent>) = removed.iter()
+        .partition(|event| event.date <= last_scraped_date);
+    let stored_in_covered_dates = stored.iter()
+        .filter(|event| event.date <= last_scraped_date)
+        .count();
+    let max_removals = limit.always_allowed.max((stored_in_covered_dates as f64 * limit.max_share) as usize);
+    if covered.len() > max_removals {
+        return Err(ReconcileError{
+            message: format!("Refusing to remove {} of {} stored events up to {}, at most {} may be removed. The scrape may be incomplete.",
+                covered.len(), stored_in_covered_dates, last_scraped_date, max_removals)
+        });
+    }
+    Ok(Removals{
+        keys: covered.iter().map(|event| event.key()).collect(),
+        beyond_covered_dates: beyond.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+
+    fn event(street: &str, date: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, street.to_owned(), "Centrum".to_owned(), None, None,
+            parse_time(&format!("{}T16:00:00+00:00", date)).unwrap(), parse_time(&format!("{}T16:45:00+00:00", date)).unwrap()).unwrap()
+    }
+
+    const NO_REMOVALS_ALWAYS_ALLOWED: RemovalLimit = RemovalLimit{
+        max_share: 0.5,
+        always_allowed: 0,
+    };
+
+    #[test]
+    fn should_only_remove_within_covered_dates() {
+        let stored = vec![event("Storgatan", "2021-03-01"), event("Kungsgatan", "2021-03-02"), event("Storgatan", "2021-04-01")];
+        let scraped = vec![event("Storgatan", "2021-03-01"), event("Storgatan", "2021-03-15")];
+        let removed = vec![stored[1].clone(), stored[2].clone()];
+        let removals = removals(&removed, &stored, &scraped, &NO_REMOVALS_ALWAYS_ALLOWED).unwrap();
+        assert_eq!(vec![stored[1].key()], removals.keys);
+        assert_eq!(1, removals.beyond_covered_dates);
+    }
+
+    #[test]
+    fn should_refuse_mass_removal() {
+        let stored = vec![event("Storgatan", "2021-03-01"), event("Kungsgatan", "2021-03-02"), event("Lilla gatan", "2021-03-03")];
+        let scraped = vec![event("Storgatan", "2021-03-03")];
+        let removed = stored.clone();
+        let result = removals(&removed, &stored, &scraped, &NO_REMOVALS_ALWAYS_ALLOWED);
+        assert!(result.unwrap_err().message.contains("Refusing to remove 3 of 3"));
+    }
+
+    #[test]
+    fn should_always_allow_a_few_removals() {
+        let stored = vec![event("Storgatan", "2021-03-01"), event("Kungsgatan", "2021-03-02")];
+        let scraped = vec![event("Storgatan", "2021-03-02")];
+        let removed = stored.clone();
+        let removals = removals(&removed, &stored, &scraped, &DEFAULT_REMOVAL_LIMIT).unwrap();
+        assert_eq!(2, removals.keys.len());
+    }
+
+    #[test]
+    fn should_remove_nothing_without_scraped_events() {
+        let stored = vec![event("Storgatan", "2021-03-01")];
+        assert_eq!(Removals::default(), removals(&stored, &stored, &[], &DEFAULT_REMOVAL_LIMIT).unwrap());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{DateTime, NaiveDate, Utc};
+use common::pickup_event::{PickUpEvent, LocationAlias};
+
+/// What stays the same about a stop when the city only changes the spelling of its street.
+#[derive(PartialEq)]
+struct StopVisits<'a> {
+    service_id: &'a str,
+    district: &'a str,
+    times: BTreeSet<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// Finds stops which are no longer scheduled while a new stop in the same district has exactly the same visits, which is how a
+/// renamed street, e.g. "Doktor Fries torg" becoming "Doktor Friis torg", shows up on the schedule pages. Each previous id is
+/// returned as an alias of the new one. Visits are only compared from `today`, which is where stored events start, up to the
+/// last scraped date, and a stop is only taken as renamed when no other stop has the same visits.
+pub fn renamed_stops(stored: &[PickUpEvent], scraped: &[PickUpEvent], today: NaiveDate) -> Vec<LocationAlias> {
+    let last_scraped_date = match scraped.iter().map(|event| event.date).max() {
+        Some(date) => date,
+        None => return Vec::new(),
+    };
+    let stored = visits_by_stop(stored.iter().filter(|event| event.date >= today && event.date <= last_scraped_date));
+    let scraped = visits_by_stop(scraped.iter().filter(|event| event.date >= today));
+    let vanished: Vec<(&str, &StopVisits)> = stored.iter()
+        .filter(|(location_id, _)| !scraped.contains_key(*location_id))
+        .map(|(location_id, visits)| (*location_id, visits))
+        .collect();
+    let appeared: Vec<(&str, &StopVisits)> = scraped.iter()
+        .filter(|(location_id, _)| !stored.contains_key(*location_id))
+        .map(|(location_id, visits)| (*location_id, visits))
+        .collect();
+    let mut aliases: Vec<LocationAlias> = Vec::new();
+    for (previous_id, visits) in &vanished {
+        let candidates: Vec<&str> = appeared.iter()
+            .filter(|(_, new_visits)| new_visits == visits)
+            .map(|(location_id, _)| *location_id)
+            .collect();
+        let same_visits = vanished.iter()
+            .filter(|(_, other_visits)| other_visits == visits)
+            .count();
+        if candidates.len() == 1 && same_visits == 1 {
+            aliases.push(LocationAlias{
+                alias_id: previous_id.to_string(),
+                location_id: candidates[0].to_owned(),
+            });
+        }
+    }
+    aliases
+}
+
+fn visits_by_stop<'a>(events: impl Iterator<Item = &'a PickUpEvent>) -> BTreeMap<&'a str, StopVisits<'a>> {
+    let mut visits: BTreeMap<&str, StopVisits> = BTreeMap::new();
+    for event in events {
+        visits.entry(event.location_id.as_str())
+            .or_insert_with(|| StopVisits{
+                service_id: &event.service_id,
+                district: &event.district,
+                times: BTreeSet::new(),
+            })
+            .times.insert((event.time_start, event.time_end));
+    }
+    visits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd(2021, 2, 15)
+    }
+
+    fn event(street: &str, district: &str, date: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, street.to_owned(), district.to_owned(), None, None,
+            parse_time(&format!("{}T16:00:00+00:00", date)).unwrap(), parse_time(&format!("{}T16:45:00+00:00", date)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_find_renamed_street() {
+        let stored = vec![
+            event("Doktor Fries torg", "Johanneberg", "2021-03-01"),
+            event("Doktor Fries torg", "Johanneberg", "2021-04-01"),
+            // After the last scraped date, so it says nothing about the rename
+            event("Doktor Fries torg", "Johanneberg", "2021-05-01"),
+            event("Storgatan", "Centrum", "2021-03-02"),
+        ];
+        let scraped = vec![
+            event("Doktor Friis torg", "Johanneberg", "2021-03-01"),
+            event("Doktor Friis torg", "Johanneberg", "2021-04-01"),
+            event("Storgatan", "Centrum", "2021-03-02"),
+        ];
+        assert_eq!(vec![LocationAlias{
+            alias_id: "farligt-avfall:johanneberg_doktor-fries-torg".to_owned(),
+            location_id: "farligt-avfall:johanneberg_doktor-friis-torg".to_owned(),
+        }], renamed_stops(&stored, &scraped, today()));
+    }
+
+    #[test]
+    fn should_find_renamed_street_with_visits_which_have_passed() {
+        let stored = vec![event("Doktor Fries torg", "Johanneberg", "2021-03-01")];
+        let scraped = vec![
+            // Still on the page, but no longer among the stored events
+            event("Doktor Friis torg", "Johanneberg", "2021-02-01"),
+            event("Doktor Friis torg", "Johanneberg", "2021-03-01"),
+        ];
+        assert_eq!(vec![LocationAlias{
+            alias_id: "farligt-avfall:johanneberg_doktor-fries-torg".to_owned(),
+            location_id: "farligt-avfall:johanneberg_doktor-friis-torg".to_owned(),
+        }], renamed_stops(&stored, &scraped, today()));
+    }
+
+    #[test]
+    fn should_not_take_stop_with_other_visits_as_renamed() {
+        let stored = vec![event("Doktor Fries torg", "Johanneberg", "2021-03-01")];
+        let scraped = vec![event("Doktor Friis torg", "Johanneberg", "2021-03-08")];
+        assert!(renamed_stops(&stored, &scraped, today()).is_empty());
+    }
+
+    #[test]
+    fn should_not_take_stop_in_other_district_as_renamed() {
+        let stored = vec![event("Doktor Fries torg", "Johanneberg", "2021-03-01")];
+        let scraped = vec![event("Doktor Friis torg", "Centrum", "2021-03-01")];
+        assert!(renamed_stops(&stored, &scraped, today()).is_empty());
+    }
+
+    #[test]
+    fn should_not_guess_between_stops_with_the_same_visits() {
+        let stored = vec![event("Doktor Fries torg", "Johanneberg", "2021-03-01")];
+        let scraped = vec![event("Doktor Friis torg", "Johanneberg", "2021-03-01"), event("Doktor Frijs torg", "Johanneberg", "2021-03-01")];
+        assert!(renamed_stops(&stored, &scraped, today()).is_empty());
+    }
+}
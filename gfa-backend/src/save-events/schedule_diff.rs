@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use chrono::{DateTime, NaiveDate, Utc};
+use common::pickup_event::{PickUpEvent, EventKey, EventStatus, event_id};
+use common::time_format::{rfc3339, iso_date};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Changeset {
+    pub added: Vec<PickUpEvent>,
+    pub removed: Vec<PickUpEvent>,
+    pub time_changed: Vec<TimeChange>,
+    pub description_changed: Vec<DescriptionChange>,
+    pub status_changed: Vec<StatusChange>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TimeChange {
+    pub location_id: String,
+    #[serde(with = "iso_date")]
+    pub date: NaiveDate,
+    #[serde(with = "rfc3339")]
+    pub previous_start: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub previous_end: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub time_start: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub time_end: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DescriptionChange {
+    pub location_id: String,
+    #[serde(with = "iso_date")]
+    pub date: NaiveDate,
+    pub previous: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatusChange {
+    pub location_id: String,
+    #[serde(with = "iso_date")]
+    pub date: NaiveDate,
+    pub previous: EventStatus,
+    pub status: EventStatus,
+}
+
+impl Changeset {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.time_changed.is_empty() && self.description_changed.is_empty()
+            && self.status_changed.is_empty()
+    }
+}
+
+impl TimeChange {
+    /// How the visit was keyed in the events table before its time changed.
+    pub fn previous_key(&self) -> EventKey {
+        EventKey{
+            date: self.date,
+            event_id: event_id(&self.location_id, &self.previous_start),
+        }
+    }
+}
+
+/// Compares newly scraped events with the stored ones. Events are grouped on location and date, so a stop which moved to another day
+/// shows up as one removed and one added event. Within a day, visits with the same start time are matched first, and any remaining ones
+/// are matched in order of time as visits which got a new time.
+pub fn diff(stored: &[PickUpEvent], scraped: &[PickUpEvent]) -> Changeset {
+    let stored = visits_by_stop_and_date(stored);
+    let scraped = visits_by_stop_and_date(scraped);
+    let mut changeset = Changeset::default();
+    for (key, events) in &scraped {
+        let mut previous_visits: Vec<&PickUpEvent> = stored.get(key).cloned().unwrap_or_else(Vec::new);
+        let mut rescheduled: Vec<&PickUpEvent> = Vec::new();
+        for event in events {
+            match previous_visits.iter().position(|previous| previous.time_start == event.time_start) {
+                Some(index) => compare(previous_visits.remove(index), event, &mut changeset),
+                None => rescheduled.push(event),
+            }
+        }
+        let rescheduled_count = rescheduled.len();
+        for (index, event) in rescheduled.into_iter().enumerate() {
+            match previous_visits.get(index) {
+                Some(previous) => compare(previous, event, &mut changeset),
+                None => changeset.added.push(event.clone()),
+            }
+        }
+        changeset.removed.extend(previous_visits.iter()
+            .skip(rescheduled_count)
+            .map(|event| (*event).clone()));
+    }
+    for (_, events) in stored.iter().filter(|(key, _)| !scraped.contains_key(*key)) {
+        changeset.removed.extend(events.iter().map(|event| (*event).clone()));
+    }
+    changeset
+}
+
+fn visits_by_stop_and_date(events: &[PickUpEvent]) -> BTreeMap<(&str, NaiveDate), Vec<&PickUpEvent>> {
+    let mut visits: BTreeMap<(&str, NaiveDate), Vec<&PickUpEvent>> = BTreeMap::new();
+    for event in events {
+        visits.entry((event.location_id.as_str(), event.date)).or_default().push(event);
+    }
+    for events in visits.values_mut() {
+        events.sort();
+    }
+    visits
+}
+
+fn compare(previous: &PickUpEvent, event: &PickUpEvent, changeset: &mut Changeset) {
+    if previous.time_start != event.time_start || previous.time_end != event.time_end {
+        changeset.time_changed.push(TimeChange{
+            location_id: event.location_id.clone(),
+            date: event.date,
+            previous_start: previous.time_start,
+            previous_end: previous.time_end,
+            time_start: event.time_start,
+            time_end: event.time_end,
+        });
+    }
+    if previous.description != event.description {
+        changeset.description_changed.push(DescriptionChange{
+            location_id: event.location_id.clone(),
+            date: event.date,
+            previous: previous.description.clone(),
+            description: event.description.clone(),
+        });
+    }
+    if previous.status != event.status {
+        changeset.status_changed.push(StatusChange{
+            location_id: event.location_id.clone(),
+            date: event.date,
+            previous: previous.status,
+            status: event.status,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pickup_event::{FARLIGT_AVFALL_SERVICE_ID, parse_time};
+
+    fn event(street: &str, description: Option<&str>, time_start: &str, time_end: &str) -> PickUpEvent {
+        PickUpEvent::new(FARLIGT_AVFALL_SERVICE_ID, street.to_owned(), "Centrum".to_owned(), None, description.map(|description| description.to_owned()), parse_time(time_start).unwrap(), parse_time(time_end).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn should_find_nothing_when_schedules_are_equal() {
+        let stored = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let scraped = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        assert!(diff(&stored, &scraped).is_empty());
+    }
+
+    #[test]
+    fn should_find_added_and_removed_events() {
+        let stored = vec![
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+            event("Lillgatan", None, "2021-03-02T18:00:00+01:00", "2021-03-02T18:45:00+01:00"),
+        ];
+        let scraped = vec![
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+            event("Lillgatan", None, "2021-03-09T18:00:00+01:00", "2021-03-09T18:45:00+01:00"),
+        ];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(1, changeset.added.len());
+        assert_eq!(NaiveDate::from_ymd(2021, 3, 9), changeset.added[0].date);
+        assert_eq!(1, changeset.removed.len());
+        assert_eq!(NaiveDate::from_ymd(2021, 3, 2), changeset.removed[0].date);
+        assert!(changeset.time_changed.is_empty());
+    }
+
+    #[test]
+    fn should_find_time_changes() {
+        let stored = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let scraped = vec![event("Storgatan", None, "2021-03-01T19:00:00+01:00", "2021-03-01T19:45:00+01:00")];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(1, changeset.time_changed.len());
+        assert_eq!("2021-03-01T17:00:00+00:00", changeset.time_changed[0].previous_start.to_rfc3339());
+        assert_eq!("2021-03-01T18:00:00+00:00", changeset.time_changed[0].time_start.to_rfc3339());
+        assert!(changeset.added.is_empty());
+        assert!(changeset.removed.is_empty());
+    }
+
+    #[test]
+    fn should_find_description_changes() {
+        let stored = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let scraped = vec![event("Storgatan", Some("vid skolan"), "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(vec![DescriptionChange{
+            location_id: "farligt-avfall:centrum_storgatan".to_owned(),
+            date: NaiveDate::from_ymd(2021, 3, 1),
+            previous: None,
+            description: Some("vid skolan".to_owned()),
+        }], changeset.description_changed);
+        assert!(changeset.time_changed.is_empty());
+    }
+
+    #[test]
+    fn should_find_status_changes() {
+        let stored = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let scraped = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00").with_status(EventStatus::Cancelled)];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(vec![StatusChange{
+            location_id: "farligt-avfall:centrum_storgatan".to_owned(),
+            date: NaiveDate::from_ymd(2021, 3, 1),
+            previous: EventStatus::Scheduled,
+            status: EventStatus::Cancelled,
+        }], changeset.status_changed);
+        assert!(!changeset.is_empty());
+    }
+
+    #[test]
+    fn should_keep_several_visits_on_the_same_day_apart() {
+        let stored = vec![
+            event("Storgatan", None, "2021-03-01T10:00:00+01:00", "2021-03-01T10:45:00+01:00"),
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+        ];
+        let scraped = vec![
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+            event("Storgatan", None, "2021-03-01T10:00:00+01:00", "2021-03-01T10:45:00+01:00"),
+        ];
+        assert!(diff(&stored, &scraped).is_empty());
+    }
+
+    #[test]
+    fn should_find_removed_visit_on_the_same_day() {
+        let stored = vec![
+            event("Storgatan", None, "2021-03-01T10:00:00+01:00", "2021-03-01T10:45:00+01:00"),
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+        ];
+        let scraped = vec![event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00")];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(vec![stored[0].clone()], changeset.removed);
+        assert!(changeset.added.is_empty());
+        assert!(changeset.time_changed.is_empty());
+    }
+
+    #[test]
+    fn should_find_added_visit_and_time_change_on_the_same_day() {
+        let stored = vec![event("Storgatan", None, "2021-03-01T10:00:00+01:00", "2021-03-01T10:45:00+01:00")];
+        let scraped = vec![
+            event("Storgatan", None, "2021-03-01T11:00:00+01:00", "2021-03-01T11:45:00+01:00"),
+            event("Storgatan", None, "2021-03-01T18:00:00+01:00", "2021-03-01T18:45:00+01:00"),
+        ];
+        let changeset = diff(&stored, &scraped);
+        assert_eq!(1, changeset.time_changed.len());
+        assert_eq!(stored[0].key(), changeset.time_changed[0].previous_key());
+        assert_eq!(vec![scraped[1].clone()], changeset.added);
+        assert!(changeset.removed.is_empty());
+    }
+}
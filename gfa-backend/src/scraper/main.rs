@@ -1,14 +1,22 @@
 use lambda::{handler_fn, Context};
 use simple_logger::{SimpleLogger};
-use log::{self, error, debug, LevelFilter};
-use futures::executor::block_on;
+use log::{self, error, debug, info, warn, LevelFilter};
+use serde::{Serialize, Deserialize};
 use serde_json::{json, Value};
-use std::fmt;
-use std::error;
+use std::{env, fmt, error, collections::BTreeMap, path::PathBuf, str::FromStr, time::Duration};
 use common::pickup_event::PickUpEvent;
+use common::scraper::page_fetcher::{self, FetchConfig, FetchOutcome};
+use common::scraper::page_parser::{SnippetDiagnostic, Severity};
+use common::scraper::page_fingerprint::{self, PageFingerprint};
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+use common::city_profile::{CityProfile, SourceProfile, ParserKind};
+use rusoto_core::Region;
+use common::scraper::page_source::{PageSource, HttpPageSource, ReplayPageSource, RecordingPageSource, Validators};
+use common::scraper::schedule_source::{ScheduleSource, GoteborgSnippetsSource};
+use page_archive::{PageArchive, S3PageArchive, FsPageArchive};
 
-mod page_fetcher;
-mod page_parser;
+mod page_archive;
 
 #[derive(fmt::Debug)]
 pub struct GfaScraperError {
@@ -27,6 +35,66 @@ impl error::Error for GfaScraperError {
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+const DEFAULT_MAX_FAILURE_RATIO: f64 = 0.1;
+
+/// `unchanged` is set when the pages of every source are known to be the same as in the last successful run, in which case there
+/// are no events and the step function skips saving. `incomplete` is set when some pages could not be fetched, so that save-events
+/// keeps stored events which are missing from the scrape. `runs` are the archived runs, which the step function marks as the latest
+/// once the events are saved.
+#[derive(fmt::Debug, Serialize)]
+struct ScrapeOutput {
+    unchanged: bool,
+    incomplete: bool,
+    events: Vec<PickUpEvent>,
+    runs: Vec<ArchivedRun>,
+}
+
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ArchivedRun {
+    service_id: String,
+    run_id: String,
+}
+
+impl ScrapeOutput {
+    fn changed(events: Vec<PickUpEvent>, run: Option<ArchivedRun>) -> Self {
+        ScrapeOutput{
+            unchanged: false,
+            incomplete: false,
+            events,
+            runs: run.into_iter().collect(),
+        }
+    }
+
+    fn unchanged() -> Self {
+        ScrapeOutput{
+            unchanged: true,
+            incomplete: false,
+            events: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Sources which are unchanged add no events, so their stored events are left as they are by save-events.
+    fn merge(outputs: Vec<ScrapeOutput>) -> Self {
+        let mut merged = ScrapeOutput::unchanged();
+        for mut output in outputs {
+            merged.unchanged &= output.unchanged;
+            merged.incomplete |= output.incomplete;
+            merged.events.append(&mut output.events);
+            merged.runs.append(&mut output.runs);
+        }
+        merged
+    }
+}
+
+struct ScrapeRun<'a> {
+    run_id: Option<&'a str>,
+    config: FetchConfig,
+    max_failure_ratio: f64,
+    /// Local date in the city, used to infer the years of dates on the schedule pages.
+    scrape_date: NaiveDate,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let _log = SimpleLogger::new()
@@ -37,11 +105,188 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
-async fn handle_request(_event: Value, _c: Context) -> Result<Value, Error> {
-    debug!("About to load pages");
-    let pages_to_scrape = block_on(page_fetcher::obtain_pages());
+async fn handle_request(event: Value, _c: Context) -> Result<Value, Error> {
+    let profile = CityProfile::from_env()?;
+    // Invoked with {"mark_latest": [...]} once the events of the runs are saved, so that a run whose events could not be saved
+    // is not taken as unchanged by the next run
+    if let Some(runs) = event.get("mark_latest") {
+        let runs: Vec<ArchivedRun> = serde_json::from_value(runs.clone())?;
+        for run in &runs {
+            mark_latest(run).await?;
+        }
+        return Ok(json!({}));
+    }
+    // Every source of the profile is scraped, unless SERVICE_ID or {"service_id": "..."} selects one of them
+    let service_id = event.get("service_id")
+        .and_then(|service_id| service_id.as_str())
+        .map(|service_id| service_id.to_owned())
+        .or_else(|| env::var("SERVICE_ID").ok());
+    let sources: Vec<&SourceProfile> = match service_id {
+        Some(service_id) => match profile.source(&service_id) {
+            Some(source_profile) => vec![source_profile],
+            None => return Err(Box::new(GfaScraperError{
+                message: format!("City profile {} has no source for service {}", profile.id, service_id)
+            }))
+        },
+        None => profile.sources.iter().collect(),
+    };
+    // Pages archived by an earlier run can be parsed again, e.g. after a parser fix, by invoking with {"run_id": "...", "service_id": "..."}
+    let run_id = event.get("run_id").and_then(|run_id| run_id.as_str());
+    if run_id.is_some() && sources.len() > 1 {
+        return Err(Box::new(GfaScraperError{
+            message: "Each service is archived separately, so parsing an archived run needs a service_id".to_owned()
+        }));
+    }
+    let config = fetch_config_from_env()?;
+    let run = ScrapeRun{
+        run_id,
+        max_failure_ratio: match env::var("MAX_FAILURE_RATIO") {
+            Ok(ratio) => f64::from_str(&ratio)?,
+            Err(_) => DEFAULT_MAX_FAILURE_RATIO,
+        },
+        scrape_date: profile.local_date(Utc::now()),
+        config,
+    };
+    let mut outputs: Vec<ScrapeOutput> = Vec::new();
+    for source_profile in &sources {
+        outputs.push(scrape_source(source_profile, profile.time_zone, &run, sources.len() > 1).await?);
+    }
+    Ok(json!(ScrapeOutput::merge(outputs)))
+}
+
+async fn scrape_source(source_profile: &SourceProfile, time_zone: Tz, run: &ScrapeRun<'_>, several_sources: bool) -> Result<ScrapeOutput, Error> {
+    let base_url = env::var("SOURCE_BASE_URL").unwrap_or_else(|_| source_profile.base_url.clone());
+    // When several sources are scraped, each of them is replayed from and recorded to a subdirectory named after its service
+    let directory = |directory: String| match several_sources {
+        true => PathBuf::from(directory).join(&source_profile.service_id),
+        false => PathBuf::from(directory),
+    };
+    match (env::var("REPLAY_DIR"), env::var("RECORD_DIR")) {
+        (Ok(replay_dir), _) => scrape_with(ReplayPageSource::new(directory(replay_dir))?, source_profile, time_zone, run).await,
+        (Err(_), Ok(record_dir)) => scrape_with(RecordingPageSource::new(HttpPageSource::new(&base_url, run.config.timeout)?, directory(record_dir))?, source_profile, time_zone, run).await,
+        (Err(_), Err(_)) => scrape_with(HttpPageSource::new(&base_url, run.config.timeout)?, source_profile, time_zone, run).await,
+    }
+}
+
+async fn scrape_with<P: PageSource + Sync>(pages: P, source_profile: &SourceProfile, time_zone: Tz, run: &ScrapeRun<'_>) -> Result<ScrapeOutput, Error> {
+    match source_profile.parser {
+        ParserKind::GoteborgSnippets => scrape(&GoteborgSnippetsSource::new(pages, source_profile, time_zone), run).await,
+    }
+}
+
+async fn scrape<S: ScheduleSource + Sync>(source: &S, run: &ScrapeRun<'_>) -> Result<ScrapeOutput, Error> {
+    let archive = archive_from_env(source.service_id())?;
+    if let Some(run_id) = run.run_id {
+        let archive = match &archive {
+            Some(archive) => archive,
+            None => return Err(Box::new(GfaScraperError{
+                message: "Cannot parse an archived run without ARCHIVE_BUCKET or ARCHIVE_DIR".to_owned()
+            }))
+        };
+        let pages = page_archive::load_run(archive.as_ref(), run_id).await?;
+        return Ok(ScrapeOutput::changed(parse_pages(source, pages, 0, run)?, None));
+    }
+    let latest = match &archive {
+        Some(archive) => page_archive::latest_manifest(archive.as_ref()).await?,
+        None => None,
+    };
+    // Events stored by an older version may be gone, e.g. after the events table was recreated, so every page is fetched and parsed
+    let latest = match latest {
+        Some(latest) if latest.schema_version != page_archive::SCHEMA_VERSION => {
+            info!("Run {} has schema version {}, ingesting every {} page for version {}", latest.run_id, latest.schema_version,
+                source.service_id(), page_archive::SCHEMA_VERSION);
+            None
+        },
+        latest => latest,
+    };
+    let previous_validators = latest.as_ref()
+        .map(|latest| latest.validators.clone())
+        .unwrap_or_default();
+    let fetched = match fetch_pages(source, &run.config, &previous_validators).await? {
+        FetchOutcome::Pages(fetched) => fetched,
+        FetchOutcome::Unchanged => {
+            info!("No {} page has been modified since the last run, skipping parsing", source.service_id());
+            return Ok(ScrapeOutput::unchanged());
+        }
+    };
+    let missing_pages = fetched.failed.len();
+    let incomplete = missing_pages > 0;
+    let archive = match archive {
+        Some(archive) => archive,
+        None => return Ok(ScrapeOutput{
+            incomplete,
+            ..ScrapeOutput::changed(parse_pages(source, fetched.pages, missing_pages, run)?, None)
+        }),
+    };
+    let run_id = Utc::now().format("%Y-%m-%dT%H-%M-%SZ").to_string();
+    let manifest = page_archive::archive_run(archive.as_ref(), &run_id, &fetched.pages, fetched.validators).await?;
+    if let Some(latest) = latest {
+        if latest.has_same_content(&manifest) {
+            info!("Pages in run {} are identical to run {}, skipping parsing", run_id, latest.run_id);
+            // Keep the validators from this run, so that the next run can use conditional requests
+            page_archive::mark_latest(archive.as_ref(), &manifest).await?;
+            return Ok(ScrapeOutput::unchanged());
+        }
+    }
+    let all_events = parse_pages(source, fetched.pages, missing_pages, run)?;
+    // An incomplete run is not marked as the latest, so that the next run fetches the missing pages even if the others are unchanged
+    let run = match incomplete {
+        true => None,
+        false => Some(ArchivedRun{
+            service_id: source.service_id().to_owned(),
+            run_id,
+        }),
+    };
+    Ok(ScrapeOutput{
+        incomplete,
+        ..ScrapeOutput::changed(all_events, run)
+    })
+}
+
+async fn mark_latest(run: &ArchivedRun) -> Result<(), Error> {
+    let archive = match archive_from_env(&run.service_id)? {
+        Some(archive) => archive,
+        None => return Err(Box::new(GfaScraperError{
+            message: format!("Cannot mark run {} as the latest without ARCHIVE_BUCKET or ARCHIVE_DIR", run.run_id)
+        }))
+    };
+    let manifest = page_archive::run_manifest(archive.as_ref(), &run.run_id).await?;
+    page_archive::mark_latest(archive.as_ref(), &manifest).await?;
+    info!("Marked run {} as the latest {} run", run.run_id, run.service_id);
+    Ok(())
+}
+
+/// Each service is archived separately, under a prefix or subdirectory named after it.
+fn archive_from_env(service_id: &str) -> Result<Option<Box<dyn PageArchive + Send + Sync>>, Error> {
+    if let Ok(bucket) = env::var("ARCHIVE_BUCKET") {
+        let region = Region::from_str(&env::var("AWS_REGION")?)?;
+        return Ok(Some(Box::new(S3PageArchive::new(&bucket, service_id, region))));
+    }
+    if let Ok(directory) = env::var("ARCHIVE_DIR") {
+        return Ok(Some(Box::new(FsPageArchive::new(PathBuf::from(directory).join(service_id)))));
+    }
+    Ok(None)
+}
+
+fn fetch_config_from_env() -> Result<FetchConfig, Error> {
+    let mut config = FetchConfig::default();
+    if let Ok(max_attempts) = env::var("FETCH_MAX_ATTEMPTS") {
+        config.max_attempts = u32::from_str(&max_attempts)?;
+    }
+    if let Ok(max_concurrency) = env::var("FETCH_MAX_CONCURRENCY") {
+        config.max_concurrency = usize::from_str(&max_concurrency)?;
+    }
+    if let Ok(timeout) = env::var("FETCH_TIMEOUT_SECONDS") {
+        config.timeout = Duration::from_secs(u64::from_str(&timeout)?);
+    }
+    Ok(config)
+}
+
+async fn fetch_pages<S: ScheduleSource + Sync>(source: &S, config: &FetchConfig, previous_validators: &BTreeMap<String, Validators>) -> Result<FetchOutcome, Error> {
+    debug!("About to load {} pages", source.service_id());
+    let pages_to_scrape = source.fetch_pages(config, previous_validators).await;
     let pages_to_scrape = match pages_to_scrape {
-        Ok(pages) => pages,
+        Ok(outcome) => outcome,
         Err(e) => {
             error!("{}", e);
             return Err(Box::new(GfaScraperError{
@@ -50,10 +295,18 @@ async fn handle_request(_event: Value, _c: Context) -> Result<Value, Error> {
         }
     };
     debug!("Finished loading all pages");
+    Ok(pages_to_scrape)
+}
+
+/// `missing_pages` is the number of schedule pages which could not be fetched.
+fn parse_pages<S: ScheduleSource>(source: &S, pages: Vec<Vec<u8>>, missing_pages: usize, run: &ScrapeRun<'_>) -> Result<Vec<PickUpEvent>, Error> {
     let mut all_events: Vec::<PickUpEvent> = Vec::new();
-    for page in pages_to_scrape {
-        let mut events = match page_parser::parse_page(page) {
-            Ok(events) => events,
+    let mut diagnostics: Vec::<SnippetDiagnostic> = Vec::new();
+    let mut fingerprints: Vec::<PageFingerprint> = Vec::new();
+    let mut total_snippets = 0;
+    for (page_index, page) in pages.into_iter().enumerate() {
+        let mut parsed = match source.parse_page(page, page_index, run.scrape_date) {
+            Ok(parsed) => parsed,
             Err(error) => {
                 error!("{}", error);
                 return Err(Box::new(GfaScraperError{
@@ -61,8 +314,127 @@ async fn handle_request(_event: Value, _c: Context) -> Result<Value, Error> {
                 })) 
             }
         };
-        all_events.append(&mut events);
+        all_events.append(&mut parsed.events);
+        diagnostics.append(&mut parsed.diagnostics);
+        total_snippets += parsed.total_snippets;
+        fingerprints.push(parsed.fingerprint);
     }
     debug!("Finished parsing pages");
-    Ok(json!(all_events))
+    // Snippets on pages which could not be fetched count as failed, so that the failure ratio decides whether the run goes on
+    let missing_snippets = match (missing_pages, fingerprints.iter().find_map(|fingerprint| fingerprint.announced_total)) {
+        (0, _) => 0,
+        (_, Some(announced_total)) => (announced_total as usize).saturating_sub(total_snippets),
+        (_, None) => missing_pages * page_fetcher::PAGE_SIZE,
+    };
+    if missing_snippets > 0 {
+        warn!("{} pages could not be fetched, counting their {} snippets as failed", missing_pages, missing_snippets);
+    }
+    // Checked before the failure ratio, since a redesigned page usually fails every snippet or has none at all
+    if let Err(e) = page_fingerprint::check_layout(&fingerprints, total_snippets + missing_snippets) {
+        error!("{}. Fingerprints: {}", e, json!(fingerprints));
+        return Err(Box::new(e));
+    }
+    for diagnostic in &diagnostics {
+        warn!("{}", diagnostic);
+    }
+    let failed_snippets = diagnostics.iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    check_failure_ratio(failed_snippets + missing_snippets, total_snippets + missing_snippets, run.max_failure_ratio)?;
+    Ok(all_events)
+}
+
+fn check_failure_ratio(failed: usize, total: usize, max_failure_ratio: f64) -> Result<(), GfaScraperError> {
+    if total == 0 || failed as f64 / total as f64 <= max_failure_ratio {
+        return Ok(());
+    }
+    Err(GfaScraperError{
+        message: format!("Failed to parse {} of {} snippets, which is more than the allowed ratio of {}", failed, total, max_failure_ratio)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::scraper::test_resources;
+
+    #[tokio::test]
+    async fn should_scrape_recorded_pages() {
+        let profile = CityProfile::goteborg();
+        let source = GoteborgSnippetsSource::new(ReplayPageSource::new(test_resources()).unwrap(), &profile.sources[0], profile.time_zone);
+        let pages = match fetch_pages(&source, &FetchConfig::default(), &BTreeMap::new()).await.unwrap() {
+            FetchOutcome::Pages(fetched) => fetched.pages,
+            FetchOutcome::Unchanged => panic!("Nothing to compare with"),
+        };
+        let run = ScrapeRun{
+            run_id: None,
+            config: FetchConfig::default(),
+            max_failure_ratio: DEFAULT_MAX_FAILURE_RATIO,
+            scrape_date: profile.local_date(Utc::now()),
+        };
+        let events = parse_pages(&source, pages, 0, &run).unwrap();
+        // The last page only has 27 of the 177 items
+        assert_eq!(5 * 39 + 36, events.len());
+    }
+
+    #[test]
+    fn should_fail_when_layout_has_changed() {
+        let profile = CityProfile::goteborg();
+        let directory = test_resources();
+        let page = std::fs::read(directory.join("body_without_items.html")).unwrap();
+        let source = GoteborgSnippetsSource::new(ReplayPageSource::new(directory).unwrap(), &profile.sources[0], profile.time_zone);
+        let run = ScrapeRun{
+            run_id: None,
+            config: FetchConfig::default(),
+            max_failure_ratio: DEFAULT_MAX_FAILURE_RATIO,
+            scrape_date: profile.local_date(Utc::now()),
+        };
+        let error = parse_pages(&source, vec![page], 0, &run).unwrap_err();
+        assert!(error.to_string().starts_with("Layout changed"));
+    }
+
+    #[tokio::test]
+    async fn should_count_snippets_of_missing_pages_as_failed() {
+        let profile = CityProfile::goteborg();
+        let source = GoteborgSnippetsSource::new(ReplayPageSource::new(test_resources()).unwrap(), &profile.sources[0], profile.time_zone);
+        let mut pages = match fetch_pages(&source, &FetchConfig::default(), &BTreeMap::new()).await.unwrap() {
+            FetchOutcome::Pages(fetched) => fetched.pages,
+            FetchOutcome::Unchanged => panic!("Nothing to compare with"),
+        };
+        pages.remove(1);
+        let run = |max_failure_ratio: f64| ScrapeRun{
+            run_id: None,
+            config: FetchConfig::default(),
+            max_failure_ratio,
+            scrape_date: profile.local_date(Utc::now()),
+        };
+        // 30 of the 177 snippets are on the missing page
+        assert!(parse_pages(&source, pages.clone(), 1, &run(0.1)).is_err());
+        assert_eq!(4 * 39 + 36, parse_pages(&source, pages, 1, &run(0.2)).unwrap().len());
+    }
+
+    #[test]
+    fn should_merge_outputs_of_all_sources() {
+        let event = PickUpEvent::new("farligt-avfall", "Storgatan".to_owned(), "Centrum".to_owned(), None, None,
+            Utc::now(), Utc::now() + chrono::Duration::minutes(45)).unwrap();
+        let run = ArchivedRun{
+            service_id: "farligt-avfall".to_owned(),
+            run_id: "run-1".to_owned(),
+        };
+        let merged = ScrapeOutput::merge(vec![ScrapeOutput::unchanged(), ScrapeOutput::changed(vec![event], Some(run.clone()))]);
+        assert!(!merged.unchanged);
+        assert_eq!(1, merged.events.len());
+        assert_eq!(vec![run], merged.runs);
+        assert!(ScrapeOutput::merge(vec![ScrapeOutput::unchanged(), ScrapeOutput::unchanged()]).unchanged);
+    }
+
+    #[test]
+    fn should_accept_a_few_failed_snippets() {
+        assert!(check_failure_ratio(1, 40, 0.1).is_ok());
+    }
+
+    #[test]
+    fn should_reject_too_many_failed_snippets() {
+        assert!(check_failure_ratio(5, 40, 0.1).is_err());
+    }
 }
@@ -0,0 +1,336 @@
+use std::{fmt, error, fs, collections::{BTreeMap, HashMap}, path::PathBuf};
+use async_trait::async_trait;
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{S3, S3Client, PutObjectRequest, GetObjectRequest, GetObjectError};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use tokio::io::AsyncReadExt;
+use common::scraper::page_source::Validators;
+
+const LATEST_MANIFEST_KEY: &str = "latest.json";
+/// Version of how scraped events are stored. Bump it when stored events have to be written again, e.g. when the events table is
+/// recreated with a new key, so that the first run after the deploy parses every page even if none of them has changed.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(fmt::Debug)]
+pub struct PageArchiveError {
+    pub message: String,
+}
+impl fmt::Display for PageArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl error::Error for PageArchiveError {}
+
+/// Key-value storage for archived pages and run manifests.
+#[async_trait]
+pub trait PageArchive {
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), PageArchiveError>;
+    /// Returns `None` if nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PageArchiveError>;
+}
+
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedPage {
+    pub key: String,
+    pub sha256: String,
+}
+
+/// Lists the pages fetched during one scraper run, in the order they were fetched.
+#[derive(fmt::Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub run_id: String,
+    pub pages: Vec<ArchivedPage>,
+    /// HTTP validators for each fetched path, used for conditional requests in the next run.
+    #[serde(default)]
+    pub validators: BTreeMap<String, Validators>,
+    /// Manifests written before the version was introduced have version 0.
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+impl RunManifest {
+    pub fn has_same_content(&self, other: &RunManifest) -> bool {
+        self.schema_version == other.schema_version && self.pages.len() == other.pages.len() && self.pages.iter()
+            .zip(other.pages.iter())
+            .all(|(page, other_page)| page.sha256 == other_page.sha256)
+    }
+}
+
+pub fn content_hash(content: &[u8]) -> String {
+    let mut hash = Sha256::new();
+    hash.update(content);
+    format!("{:x}", hash.finalize())
+}
+
+/// Stores all pages and a manifest for them under `run_id`.
+pub async fn archive_run<A: PageArchive + ?Sized>(archive: &A, run_id: &str, pages: &[Vec<u8>], validators: BTreeMap<String, Validators>) -> Result<RunManifest, PageArchiveError> {
+    let mut archived_pages: Vec<ArchivedPage> = Vec::new();
+    for (index, page) in pages.iter().enumerate() {
+        let key = format!("{}/page-{}.html", run_id, index);
+        archive.put(&key, page.clone()).await?;
+        archived_pages.push(ArchivedPage{
+            key,
+            sha256: content_hash(page),
+        });
+    }
+    let manifest = RunManifest{
+        run_id: run_id.to_owned(),
+        pages: archived_pages,
+        validators,
+        schema_version: SCHEMA_VERSION,
+    };
+    archive.put(&manifest_key(run_id), serialize_manifest(&manifest)?).await?;
+    Ok(manifest)
+}
+
+/// Records `manifest` as the last run whose events were saved successfully.
+pub async fn mark_latest<A: PageArchive + ?Sized>(archive: &A, manifest: &RunManifest) -> Result<(), PageArchiveError> {
+    archive.put(LATEST_MANIFEST_KEY, serialize_manifest(manifest)?).await
+}
+
+pub async fn latest_manifest<A: PageArchive + ?Sized>(archive: &A) -> Result<Option<RunManifest>, PageArchiveError> {
+    match archive.get(LATEST_MANIFEST_KEY).await? {
+        Some(content) => Ok(Some(deserialize_manifest(&content)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn run_manifest<A: PageArchive + ?Sized>(archive: &A, run_id: &str) -> Result<RunManifest, PageArchiveError> {
+    match archive.get(&manifest_key(run_id)).await? {
+        Some(content) => deserialize_manifest(&content),
+        None => Err(PageArchiveError{
+            message: format!("No archived run with id: {}", run_id)
+        })
+    }
+}
+
+/// Reads back all pages archived under `run_id`, so that they can be parsed again.
+pub async fn load_run<A: PageArchive + ?Sized>(archive: &A, run_id: &str) -> Result<Vec<Vec<u8>>, PageArchiveError> {
+    let manifest = run_manifest(archive, run_id).await?;
+    let mut pages: Vec<Vec<u8>> = Vec::new();
+    for archived_page in manifest.pages {
+        match archive.get(&archived_page.key).await? {
+            Some(page) if content_hash(&page) == archived_page.sha256 => pages.push(page),
+            Some(_) => return Err(PageArchiveError{
+                message: format!("Archived page {} does not match its hash", archived_page.key)
+            }),
+            None => return Err(PageArchiveError{
+                message: format!("Archived page {} is missing", archived_page.key)
+            }),
+        }
+    }
+    Ok(pages)
+}
+
+fn manifest_key(run_id: &str) -> String {
+    format!("{}/manifest.json", run_id)
+}
+
+fn serialize_manifest(manifest: &RunManifest) -> Result<Vec<u8>, PageArchiveError> {
+    match serde_json::to_vec_pretty(manifest) {
+        Ok(content) => Ok(content),
+        Err(e) => Err(PageArchiveError{
+            message: format!("Could not serialize manifest for run {}: {}", manifest.run_id, e)
+        })
+    }
+}
+
+fn deserialize_manifest(content: &[u8]) -> Result<RunManifest, PageArchiveError> {
+    match serde_json::from_slice(content) {
+        Ok(manifest) => Ok(manifest),
+        Err(e) => Err(PageArchiveError{
+            message: format!("Malformed run manifest: {}", e)
+        })
+    }
+}
+
+/// Keeps the archive in an S3 bucket, with all keys under `prefix`.
+pub struct S3PageArchive {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3PageArchive {
+    pub fn new(bucket: &str, prefix: &str, region: Region) -> Self {
+        S3PageArchive{
+            client: S3Client::new(region),
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}/{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl PageArchive for S3PageArchive {
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), PageArchiveError> {
+        let mut metadata: HashMap<String, String> = HashMap::new();
+        metadata.insert("sha256".to_owned(), content_hash(&content));
+        let content_type = match key.ends_with(".json") {
+            true => "application/json",
+            false => "text/html",
+        };
+        let key = self.object_key(key);
+        match self.client.put_object(PutObjectRequest{
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            body: Some(content.into()),
+            content_type: Some(content_type.to_owned()),
+            metadata: Some(metadata),
+            ..Default::default()
+        }).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(PageArchiveError{
+                message: format!("Could not write s3://{}/{}: {}", self.bucket, key, e)
+            })
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PageArchiveError> {
+        let key = self.object_key(key);
+        let output = match self.client.get_object(GetObjectRequest{
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            ..Default::default()
+        }).await {
+            Ok(output) => output,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(e) => return Err(PageArchiveError{
+                message: format!("Could not read s3://{}/{}: {}", self.bucket, key, e)
+            })
+        };
+        let mut content: Vec<u8> = Vec::new();
+        if let Some(body) = output.body {
+            if let Err(e) = body.into_async_read().read_to_end(&mut content).await {
+                return Err(PageArchiveError{
+                    message: format!("Could not read body of s3://{}/{}: {}", self.bucket, key, e)
+                });
+            }
+        }
+        Ok(Some(content))
+    }
+}
+
+/// Keeps the archive in a local directory, with keys as relative paths.
+pub struct FsPageArchive {
+    directory: PathBuf,
+}
+
+impl FsPageArchive {
+    pub fn new(directory: PathBuf) -> Self {
+        FsPageArchive{
+            directory,
+        }
+    }
+}
+
+#[async_trait]
+impl PageArchive for FsPageArchive {
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), PageArchiveError> {
+        let path = self.directory.join(key);
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                return Err(PageArchiveError{
+                    message: format!("Could not create directory {}: {}", parent.display(), e)
+                });
+            }
+        }
+        match fs::write(&path, content) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(PageArchiveError{
+                message: format!("Could not write {}: {}", path.display(), e)
+            })
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PageArchiveError> {
+        let path = self.directory.join(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        match fs::read(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) => Err(PageArchiveError{
+                message: format!("Could not read {}: {}", path.display(), e)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_archive(name: &str) -> (FsPageArchive, PathBuf) {
+        let directory = env::temp_dir().join(format!("gfa-archive-{}-{}", name, std::process::id()));
+        (FsPageArchive::new(directory.clone()), directory)
+    }
+
+    #[test]
+    fn should_hash_content_with_sha256() {
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", content_hash(b"abc"));
+    }
+
+    #[tokio::test]
+    async fn should_archive_and_load_run() {
+        let (archive, directory) = temp_archive("load");
+        let pages = vec![b"<html>first</html>".to_vec(), b"<html>second</html>".to_vec()];
+        let manifest = archive_run(&archive, "run-1", &pages, BTreeMap::new()).await.unwrap();
+        let loaded = load_run(&archive, "run-1").await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(2, manifest.pages.len());
+        assert_eq!(content_hash(&pages[1]), manifest.pages[1].sha256);
+        assert_eq!(pages, loaded);
+    }
+
+    #[tokio::test]
+    async fn should_compare_runs_with_latest() {
+        let (archive, directory) = temp_archive("latest");
+        assert_eq!(None, latest_manifest(&archive).await.unwrap());
+        let first = archive_run(&archive, "run-1", &[b"a".to_vec(), b"b".to_vec()], BTreeMap::new()).await.unwrap();
+        mark_latest(&archive, &first).await.unwrap();
+        let same = archive_run(&archive, "run-2", &[b"a".to_vec(), b"b".to_vec()], BTreeMap::new()).await.unwrap();
+        let changed = archive_run(&archive, "run-3", &[b"a".to_vec(), b"c".to_vec()], BTreeMap::new()).await.unwrap();
+        let latest = latest_manifest(&archive).await.unwrap().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!("run-1", latest.run_id);
+        assert!(latest.has_same_content(&same));
+        assert!(!latest.has_same_content(&changed));
+    }
+
+    #[tokio::test]
+    async fn should_only_mark_run_as_latest_when_asked_to() {
+        let (archive, directory) = temp_archive("mark");
+        let first = archive_run(&archive, "run-1", &[b"a".to_vec()], BTreeMap::new()).await.unwrap();
+        mark_latest(&archive, &first).await.unwrap();
+        archive_run(&archive, "run-2", &[b"b".to_vec()], BTreeMap::new()).await.unwrap();
+        let before = latest_manifest(&archive).await.unwrap().unwrap();
+        mark_latest(&archive, &run_manifest(&archive, "run-2").await.unwrap()).await.unwrap();
+        let after = latest_manifest(&archive).await.unwrap().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!("run-1", before.run_id);
+        assert_eq!("run-2", after.run_id);
+    }
+
+    #[test]
+    fn should_not_compare_with_manifest_of_other_schema_version() {
+        let previous: RunManifest = serde_json::from_str(r#"{"run_id": "run-1", "pages": [{"key": "run-1/page-0.html", "sha256": "abc"}]}"#).unwrap();
+        let mut current = previous.clone();
+        current.schema_version = SCHEMA_VERSION;
+        assert_eq!(0, previous.schema_version);
+        assert!(!previous.has_same_content(&current));
+    }
+
+    #[tokio::test]
+    async fn should_fail_to_load_unknown_run() {
+        let (archive, _) = temp_archive("unknown");
+        assert!(load_run(&archive, "no-such-run").await.is_err());
+    }
+}
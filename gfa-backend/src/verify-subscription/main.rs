@@ -4,7 +4,7 @@ use simple_logger::SimpleLogger;
 use lambda::{handler_fn, Context};
 use aws_lambda_events::event::apigw::{ApiGatewayV2httpRequest, ApiGatewayV2httpResponse};
 use rusoto_core::Region;
-use common::subscriptions_repo::{get_subscription_by_auth_token, store_subscription};
+use common::subscriptions_repo::{SubscriptionsRepository, DynamoDbSubscriptionsRepository};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -37,31 +37,36 @@ async fn handle_request(
         }
     };
 
-    let mut subscription_from_db = match get_subscription_by_auth_token(&subscriptions_table, &region, auth_token).await {
+    let subscriptions = DynamoDbSubscriptionsRepository::new(&region, &subscriptions_table);
+    Ok(verify_subscription(&subscriptions, email, auth_token).await)
+}
+
+async fn verify_subscription(subscriptions: &dyn SubscriptionsRepository, email: &str, auth_token: &str) -> ApiGatewayV2httpResponse {
+    let mut subscription_from_db = match subscriptions.get_subscription_by_auth_token(auth_token).await {
         Ok(optional_subscription) => match optional_subscription {
             Some(subscription) => subscription,
-            None => return Ok(create_response(404, "Subscription not found".to_owned()))
+            None => return create_response(404, "Subscription not found".to_owned())
         },
         Err(error) => {
             error!("Failed to read from database: {}", error);
-            return Ok(create_response(500, "Internal error when verifying subscription".to_owned()))
+            return create_response(500, "Internal error when verifying subscription".to_owned())
         }
     };
 
     if subscription_from_db.is_authenticated {
-        return Ok(create_response(400, "Subscription already verified".to_owned()))
+        return create_response(400, "Subscription already verified".to_owned())
     }
-    if subscription_from_db.email != *email {
-        return Ok(create_response(400, "Bad auth token".to_owned()))
+    if subscription_from_db.email != email {
+        return create_response(400, "Bad auth token".to_owned())
     }
 
     subscription_from_db.verify();
 
-    match store_subscription(&subscriptions_table, &region, &subscription_from_db).await {
-        Ok(()) => Ok(create_response(200, "Successfully verified subscription".to_owned())),
+    match subscriptions.store_subscription(&subscription_from_db).await {
+        Ok(()) => create_response(200, "Successfully verified subscription".to_owned()),
         Err(error) => {
             error!("Failed to write to database: {}", error);
-            Ok(create_response(500, "Failed to write to database".to_owned()))
+            create_response(500, "Failed to write to database".to_owned())
         }
     }
 }
@@ -76,3 +81,32 @@ fn create_response(status_code: i64, body: String) -> ApiGatewayV2httpResponse {
         cookies: Vec::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::subscription::Subscription;
+    use common::subscriptions_repo::InMemorySubscriptionsRepository;
+
+    #[tokio::test]
+    async fn should_verify_subscription() {
+        let subscription = Subscription::new("email@email.com", "farligt-avfall:centrum_storgatan");
+        let auth_token = subscription.auth_token.clone().unwrap();
+        let subscriptions = InMemorySubscriptionsRepository::with_subscriptions(vec![subscription]);
+        let response = verify_subscription(&subscriptions, "email@email.com", &auth_token).await;
+        assert_eq!(200, response.status_code);
+        let verified = subscriptions.get_authenticated_subscriptions("farligt-avfall:centrum_storgatan").await.unwrap();
+        assert_eq!(1, verified.len());
+        assert!(verified[0].unsubscribe_token.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_not_verify_with_token_of_other_email() {
+        let subscription = Subscription::new("email@email.com", "farligt-avfall:centrum_storgatan");
+        let auth_token = subscription.auth_token.clone().unwrap();
+        let subscriptions = InMemorySubscriptionsRepository::with_subscriptions(vec![subscription]);
+        let response = verify_subscription(&subscriptions, "other@email.com", &auth_token).await;
+        assert_eq!(400, response.status_code);
+        assert!(subscriptions.get_authenticated_subscriptions("farligt-avfall:centrum_storgatan").await.unwrap().is_empty());
+    }
+}
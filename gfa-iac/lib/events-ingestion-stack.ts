@@ -1,9 +1,10 @@
 import { Construct, Duration, RemovalPolicy } from '@aws-cdk/core';
 import { NestedStack, NestedStackProps } from '@aws-cdk/aws-cloudformation';
-import { StateMachine, TaskInput } from '@aws-cdk/aws-stepfunctions';
-import { SnsPublish } from '@aws-cdk/aws-stepfunctions-tasks';
+import { StateMachine, TaskInput, Choice, Condition, Succeed, JsonPath } from '@aws-cdk/aws-stepfunctions';
+import { SnsPublish, LambdaInvoke } from '@aws-cdk/aws-stepfunctions-tasks';
 import { Table, AttributeType, BillingMode, ProjectionType } from '@aws-cdk/aws-dynamodb';
 import { ITopic } from '@aws-cdk/aws-sns';
+import { BlockPublicAccess, Bucket } from '@aws-cdk/aws-s3';
 import { Rule, Schedule } from '@aws-cdk/aws-events';
 import { SfnStateMachine } from '@aws-cdk/aws-events-targets';
 import { GfaFunctionWithInvokeTask } from './function/gfa-function-invoke';
@@ -15,44 +16,98 @@ export interface EventsIngestionStackProps extends NestedStackProps {
 export class EventsIngestionStack extends NestedStack {
 
   public readonly eventsTable: Table;
+  public readonly aliasesTable: Table;
   public readonly locationIndex: string = 'byLocationId'; 
 
   constructor(scope: Construct, id: string, props: EventsIngestionStackProps) {
     super(scope, id, props);
 
+    // Changing the key replaces the table, which then is empty. Bump SCHEMA_VERSION in the scraper's page_archive.rs along with such
+    // a change, so that the next run ingests every page even if the schedule has not changed.
     this.eventsTable = new Table(this, 'events-db', {
       partitionKey: { name: 'event_date', type: AttributeType.STRING },
-      sortKey: { name: 'location_id', type: AttributeType.STRING },
+      sortKey: { name: 'event_id', type: AttributeType.STRING },
       billingMode: BillingMode.PAY_PER_REQUEST,
       removalPolicy: RemovalPolicy.DESTROY
     });
+    // CloudFormation cannot change the key or attributes of an existing index in place, so a change to the index has to be deployed
+    // along with a change of the table key, which recreates the table.
     this.eventsTable.addGlobalSecondaryIndex({
       indexName: this.locationIndex,
       partitionKey: {
         name: 'location_id',
         type: AttributeType.STRING,
       },
+      sortKey: {
+        name: 'event_date',
+        type: AttributeType.STRING,
+      },
       projectionType: ProjectionType.INCLUDE,
       nonKeyAttributes: [
         'street',
         'district',
+        'organisation_type',
         'description',
+        'latitude',
+        'longitude',
+        'start_time',
+        'end_time',
+        'status',
       ],
     });
 
+    // Maps ids which locations used to have to their current ids, so that subscriptions survive spelling changes
+    this.aliasesTable = new Table(this, 'aliases-db', {
+      partitionKey: { name: 'alias_id', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      removalPolicy: RemovalPolicy.RETAIN
+    });
+    this.aliasesTable.addGlobalSecondaryIndex({
+      indexName: 'byLocationId',
+      partitionKey: { name: 'location_id', type: AttributeType.STRING },
+    });
+
+    const pageArchive = new Bucket(this, 'page-archive', {
+      removalPolicy: RemovalPolicy.RETAIN,
+      blockPublicAccess: BlockPublicAccess.BLOCK_ALL,
+    });
 
     const scraper = new GfaFunctionWithInvokeTask(this, 'scraper', {
       name: 'scraper',
-      outputPath: '$.Payload'
+      outputPath: '$.Payload',
+      timeout: Duration.minutes(2),
+      environment: {
+        ARCHIVE_BUCKET: pageArchive.bucketName
+      }
     });
+    pageArchive.grantReadWrite(scraper.handler);
 
+    const saveEventsEnvironment: { [key: string]: string } = {
+      EVENTS_TABLE: this.eventsTable.tableName,
+      LOCATION_INDEX: this.locationIndex,
+      ALIASES_TABLE: this.aliasesTable.tableName,
+    };
+    const geocoderUrl = this.node.tryGetContext('geocoderUrl');
+    if (geocoderUrl) {
+      saveEventsEnvironment.GEOCODER_URL = geocoderUrl;
+    }
     const saveEvents = new GfaFunctionWithInvokeTask(this, 'save-events', {
       name: 'save-events',
-      environment: {
-        EVENTS_TABLE: this.eventsTable.tableName
-      }
+      // Keeps the runs for marking them as the latest
+      resultPath: '$.changeset',
+      // New stops are geocoded at most once per second
+      timeout: Duration.minutes(3),
+      environment: saveEventsEnvironment,
+    });
+    this.eventsTable.grantReadWriteData(saveEvents.handler);
+    this.aliasesTable.grantReadWriteData(saveEvents.handler);
+
+    // The run is only marked as the latest once its events are saved, otherwise the next run would find the pages unchanged
+    const markLatest = new LambdaInvoke(this, 'invoke-mark-latest', {
+      lambdaFunction: scraper.handler,
+      payload: TaskInput.fromObject({ 'mark_latest.$': '$.runs' }),
+      resultPath: JsonPath.DISCARD,
     });
-    this.eventsTable.grantWriteData(saveEvents.handler);
 
     const alertTask = new SnsPublish(this, 'Data ingestion alert', {
       topic: props.alertTopic,
@@ -61,8 +116,11 @@ export class EventsIngestionStack extends NestedStack {
     });
     const scrapeAndSaveFlow = new StateMachine(this, 'scrape-and-save', {
       definition: scraper.task.addCatch(alertTask)
-        .next(saveEvents.task.addCatch(alertTask)),
-      timeout: Duration.minutes(5)
+        .next(new Choice(this, 'Schedule changed?')
+          .when(Condition.booleanEquals('$.unchanged', true), new Succeed(this, 'Schedule unchanged'))
+          .otherwise(saveEvents.task.addCatch(alertTask)
+            .next(markLatest.addCatch(alertTask)))),
+      timeout: Duration.minutes(6)
     });
 
     new Rule(this, 'scrape-and-save-scheduled-execution', {
@@ -4,7 +4,9 @@ import { LambdaInvoke } from '@aws-cdk/aws-stepfunctions-tasks';
 import { GfaFunction, GfaFunctionProps } from "./gfa-function";
 
 export interface GfaFunctionWithInvokeTaskProps extends GfaFunctionProps {
-    outputPath?: string
+    inputPath?: string,
+    outputPath?: string,
+    resultPath?: string
 }
 
 export class GfaFunctionWithInvokeTask extends Construct {
@@ -18,7 +20,9 @@ export class GfaFunctionWithInvokeTask extends Construct {
         this.handler = new GfaFunction(this, `fn-${props.name}`, props).handler;
         this.task = new LambdaInvoke(this, `invoke-${props.name}`, {
             lambdaFunction: this.handler,
+            inputPath: props.inputPath,
             outputPath: props.outputPath,
+            resultPath: props.resultPath,
         });
     }
 }
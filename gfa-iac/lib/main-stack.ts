@@ -39,6 +39,7 @@ export class GbgFarligtAvfallStack extends Stack {
       api: apiStack.api,
       eventsTable: eventsIngestionStack.eventsTable,
       locationIndex: eventsIngestionStack.locationIndex,
+      aliasesTable: eventsIngestionStack.aliasesTable,
     });
 
     const domainName = app.node.tryGetContext('domainName');
@@ -50,11 +51,14 @@ export class GbgFarligtAvfallStack extends Stack {
       apiKey: sendgridApiKey,
       eventsTable: eventsIngestionStack.eventsTable,
       locationIndex: eventsIngestionStack.locationIndex,
+      aliasesTable: eventsIngestionStack.aliasesTable,
     });
 
     new NotifyStack(this, 'notify-stack', {
       eventsTable: eventsIngestionStack.eventsTable,
+      locationIndex: eventsIngestionStack.locationIndex,
       subscriptionsTable: subscriptionsStack.subscriptionsDb, 
+      aliasesTable: eventsIngestionStack.aliasesTable,
       apiKey: sendgridApiKey,
       emailDomain: domainName,
       unsubscribeUrl: `https://${webStack.externalDomain}/unsubscribe`,
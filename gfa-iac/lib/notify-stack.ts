@@ -10,7 +10,9 @@ import { GfaFunction } from './function/gfa-function';
 
 interface NotifyStackProps extends NestedStackProps {
     eventsTable: ITable,
+    locationIndex: string,
     subscriptionsTable: ITable,
+    aliasesTable: ITable,
     apiKey: string,
     emailDomain: string,
     unsubscribeUrl: string,
@@ -26,6 +28,8 @@ export class NotifyStack extends NestedStack {
             environment: {
                 EVENTS_TABLE: props.eventsTable.tableName,
                 SUBSCRIPTIONS_TABLE: props.subscriptionsTable.tableName,
+                LOCATION_INDEX: props.locationIndex,
+                ALIASES_TABLE: props.aliasesTable.tableName,
                 SENDGRID_API_KEY: props.apiKey,
                 EMAIL_DOMAIN: props.emailDomain,
                 UNSUBSCRIBE_URL: props.unsubscribeUrl,
@@ -33,6 +37,7 @@ export class NotifyStack extends NestedStack {
         });
         props.eventsTable.grantReadData(notify.handler);
         props.subscriptionsTable.grantReadData(notify.handler);
+        props.aliasesTable.grantReadData(notify.handler);
 
         new Rule(this, 'notify-scheduled-execution', {
             schedule: Schedule.expression('cron(0 3 * * ? *)'),
@@ -8,6 +8,7 @@ export interface StopsStackProps {
     api: HttpApi,
     eventsTable: ITable,
     locationIndex: string,
+    aliasesTable: ITable,
 }
 
 export class StopsStack extends NestedStack {
@@ -19,9 +20,11 @@ export class StopsStack extends NestedStack {
             environment: {
                 EVENTS_TABLE: props.eventsTable.tableName,
                 LOCATION_INDEX: props.locationIndex,
+                ALIASES_TABLE: props.aliasesTable.tableName,
             }
         });
         props.eventsTable.grantReadData(getStops.handler);
+        props.aliasesTable.grantReadData(getStops.handler);
 
         props.api.addRoutes({
             path: '/stops',
@@ -30,5 +33,24 @@ export class StopsStack extends NestedStack {
                 handler: getStops.handler,
             }),
         });
+
+        const getStop = new GfaFunction(this, 'get-stop', {
+            name: 'get-stop',
+            environment: {
+                EVENTS_TABLE: props.eventsTable.tableName,
+                LOCATION_INDEX: props.locationIndex,
+                ALIASES_TABLE: props.aliasesTable.tableName,
+            }
+        });
+        props.eventsTable.grantReadData(getStop.handler);
+        props.aliasesTable.grantReadData(getStop.handler);
+
+        props.api.addRoutes({
+            path: '/stops/{location_id}',
+            methods: [ HttpMethod.GET ],
+            integration: new LambdaProxyIntegration({
+                handler: getStop.handler,
+            }),
+        });
     }
 }
@@ -12,6 +12,7 @@ export interface SubscriptionsStackProps extends NestedStackProps {
     apiKey: string,
     eventsTable: Table,
     locationIndex: string,
+    aliasesTable: Table,
 }
 
 export class SubscriptionStack extends NestedStack {
@@ -47,6 +48,7 @@ export class SubscriptionStack extends NestedStack {
                 SUBSCRIPTIONS_TABLE: this.subscriptionsDb.tableName,
                 EVENTS_TABLE: props.eventsTable.tableName,
                 LOCATION_INDEX: props.locationIndex,
+                ALIASES_TABLE: props.aliasesTable.tableName,
                 VERIFY_URL: props.verifyUrl,
                 SENDGRID_API_KEY: props.apiKey,
                 EMAIL_DOMAIN: props.emailDomain,
@@ -54,6 +56,7 @@ export class SubscriptionStack extends NestedStack {
         });
         this.subscriptionsDb.grantReadWriteData(addSubscription.handler);
         props.eventsTable.grantReadData(addSubscription.handler);
+        props.aliasesTable.grantReadData(addSubscription.handler);
 
         const verifySubscription = new GfaFunction(this, 'verifySubscription', {
             name: 'verify-subscription',